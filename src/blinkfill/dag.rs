@@ -1,16 +1,80 @@
+use super::date::{self, DateFormat};
 use super::input_data_graph::{Id, InputDataGraph};
 use super::language::{
-    ColumnIndex, Direction, Occurrence, Position, StringExpression, StringIndex,
-    SubstringExpression,
+    char_offsets, slice_chars, Case, ColumnIndex, Direction, IntegerExpression, Occurrence,
+    Position, StringExpression, StringIndex, SubstringExpression,
 };
+use super::number::{self, NumberFormat};
+use super::table::Table;
+use super::token::{Token, LOOP_TOKENS};
+use super::{Conflict, Ranking};
 use crate::graph;
+use crate::par;
+use std::cmp::{self, Reverse};
 use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::iter;
 
 const EPSILON: usize = 1;
 const KAPPA: usize = 15; // BlinkFill Section 7.3
 
+// case conversions score slightly lower than verbatim copies, so that copies win when both work
+const CONVERT_PENALTY: usize = 1;
+// similarly, loops, lookups, and number and date formatting score slightly lower than substrings
+// that produce the same output
+const LOOP_PENALTY: usize = 2;
+const LOOKUP_PENALTY: usize = 2;
+const NUMBER_PENALTY: usize = 2;
+const DATE_PENALTY: usize = 2;
+// substrings of intermediate strings score lower than substrings of the input, so they are only
+// used when no substring of the input works
+const NESTED_PENALTY: usize = 2;
+// arithmetic is penalized more, and more so for larger constants, because it is more likely to fit
+// the examples by coincidence
+const ARITHMETIC_PENALTY: usize = 2;
+
+const CASES: [Case; 3] = [Case::Upper, Case::Lower, Case::Proper];
+
 type Node = usize;
 type Edge = (Node, Node);
+// the body of a loop, which is a substring of the match between two positions, possibly converted
+// to a different case
+type LoopBody = (Option<Case>, Position, Position);
+// an intermediate string for nested substrings, by its start and end in the input, with the
+// substring expressions that produce it, the positions in it by index, and its char_offsets
+type Intermediate = (
+    usize,
+    usize,
+    SubstringExpressionSet,
+    BTreeMap<usize, BTreeSet<Position>>,
+    Vec<usize>,
+);
+
+// the properties of an input data graph that ranking uses, which are expensive to compute, so that
+// they can be shared when ranking is repeated
+struct Ranker<'a> {
+    graph: &'a InputDataGraph,
+    ranking: Ranking,
+    lengths: BTreeMap<Id, usize>,
+    ranks: HashMap<Node, usize>,
+    idg_adj: BTreeMap<Node, BTreeSet<Node>>,
+    idg_inv: BTreeMap<Node, BTreeSet<Node>>,
+}
+
+impl<'a> Ranker<'a> {
+    fn new(graph: &'a InputDataGraph, ranking: Ranking) -> Self {
+        let distances = graph.distances();
+        let idg_adj = graph::adjacency_map(graph.edges());
+        let idg_inv = graph::invert_adjacency_map(&idg_adj);
+        Self {
+            graph,
+            ranking,
+            lengths: graph.lengths(),
+            ranks: graph.rank_nodes(&distances),
+            idg_adj,
+            idg_inv,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Dag {
@@ -21,19 +85,90 @@ pub struct Dag {
 }
 
 impl Dag {
-    fn new(input: &[&str], output: &str, graph: &InputDataGraph, row: usize) -> Self {
+    fn new(
+        input: &[&str],
+        output: &str,
+        graph: &InputDataGraph,
+        row: usize,
+        tables: &[Table],
+    ) -> Self {
         let mut substrings = BTreeMap::new();
-        let n = output.len();
+        // the nodes are the indices of the output, which count characters, and these are their
+        // byte offsets; the inputs are searched by byte offset, and their indices are converted to
+        // characters when positions are generated for them
+        let bounds: Vec<usize> = output
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(iter::once(output.len()))
+            .collect();
+        let n = bounds.len() - 1;
+        let offsets: Vec<Vec<usize>> = input.iter().map(|s| char_offsets(s)).collect();
+        let mut loops = generate_loop_sets(input, output);
+        let boundaries: Vec<_> = input
+            .iter()
+            .map(|s| InputDataGraph::boundaries(s, &graph.custom_tokens))
+            .collect();
+        let substring_set = |ci: usize, l: usize, r: usize| {
+            let id = Id { row, col: ci };
+            let (l, r) = (offsets[ci][l] + 1, offsets[ci][r] + 1);
+            let (l, r) = (StringIndex(l), StringIndex(r));
+            SubstringExpressionSet::generate_substring_set(id, l, r, &boundaries[ci], graph)
+        };
+        let intermediates: Vec<Vec<Intermediate>> = input
+            .iter()
+            .enumerate()
+            .map(|(ci, input_str)| {
+                intermediate_spans(input_str)
+                    .into_iter()
+                    .map(|(a, b)| {
+                        let t = &input_str[a..b];
+                        let positions = InputDataGraph::positions(t, &graph.custom_tokens);
+                        (a, b, substring_set(ci, a, b), positions, char_offsets(t))
+                    })
+                    .collect()
+            })
+            .collect();
+        let dates: Vec<_> = input.iter().map(|s| date::find_dates(s)).collect();
+        // dates in the output start and end at the boundaries of numbers and words
+        let same_kind = |a: char, b: char| {
+            (a.is_ascii_digit() && b.is_ascii_digit()) || (a.is_alphabetic() && b.is_alphabetic())
+        };
+        let at_boundaries = |i: usize, j: usize| {
+            let (i, j) = (bounds[i], bounds[j]);
+            let s = &output[i..j];
+            let start = match (output[..i].chars().last(), s.chars().next()) {
+                (Some(a), Some(b)) => !same_kind(a, b),
+                _ => true,
+            };
+            let end = match (s.chars().last(), output[j..].chars().next()) {
+                (Some(a), Some(b)) => !same_kind(a, b),
+                _ => true,
+            };
+            start && end
+        };
+
+        // the inputs converted to each case, for finding substrings that need to be converted; we
+        // search in these, so we only use the ones that keep all of the indices the same
+        let folded: Vec<Vec<(Case, String)>> = input
+            .iter()
+            .map(|input_str| {
+                CASES
+                    .iter()
+                    .map(|case| (*case, case.apply(input_str)))
+                    .filter(|(_, f)| f.len() == input_str.len() && f != input_str)
+                    .collect()
+            })
+            .collect();
 
         for i in 0..n {
             for j in i + 1..n + 1 {
-                let s = &output[i..j];
+                let s = &output[bounds[i]..bounds[j]];
+                let len = j - i;
                 // learn the constant string
                 let mut exprs = vec![ConstantString(String::from(s))];
                 // learn all substring expressions
                 for (ci, input_str) in input.iter().enumerate() {
                     // find all instances of s (including overlapping ones) in input_str
-                    let id = Id { row, col: ci };
                     let mut offset = 0;
                     while offset < input_str.len() {
                         match input_str[offset..].find(s) {
@@ -43,15 +178,151 @@ impl Dag {
                             Some(start) => {
                                 let l = offset + start;
                                 let r = l + s.len();
-                                let (l, r) = (StringIndex(l + 1), StringIndex(r + 1));
-                                let substring_exprs =
-                                    SubstringExpressionSet::generate_substring_set(id, l, r, graph);
+                                let substring_exprs = substring_set(ci, l, r);
                                 exprs.push(substring_exprs);
-                                offset = offset + start + 1;
+                                // the next match can start at the next character
+                                offset = l + s.chars().next().map_or(1, char::len_utf8);
+                            }
+                        }
+                    }
+                }
+                let copied = exprs.len() > 1;
+                // learn all substrings of intermediate strings, other than the ones that are the
+                // whole intermediate string, which are substrings of the input
+                for (ci, intermediates) in intermediates.iter().enumerate() {
+                    for (a, b, source, positions, t_offsets) in intermediates {
+                        let t = &input[ci][*a..*b];
+                        if t == s {
+                            continue;
+                        }
+                        for (l, _) in t.match_indices(s) {
+                            let r = l + s.len();
+                            let (l, r) = (t_offsets[l] + 1, t_offsets[r] + 1);
+                            if let (Some(p_l), Some(p_r)) = (positions.get(&l), positions.get(&r)) {
+                                exprs.push(NestedSet(
+                                    Box::new(source.clone()),
+                                    p_l.clone(),
+                                    p_r.clone(),
+                                    len,
+                                ));
+                            }
+                        }
+                    }
+                }
+                // learn all case conversions of substring expressions
+                for (ci, input_str) in input.iter().enumerate() {
+                    for (case, folded_str) in &folded[ci] {
+                        for (l, _) in folded_str.match_indices(s) {
+                            let r = l + s.len();
+                            // the search is only a heuristic; converting a part of a string can
+                            // give a different result than converting the whole string (for
+                            // example, with proper case), so check that converting the substring
+                            // itself gives the output, and skip substrings that are already in
+                            // the right case, because those are covered by the verbatim copies
+                            if !input_str.is_char_boundary(l) || !input_str.is_char_boundary(r) {
+                                continue;
+                            }
+                            let t = &input_str[l..r];
+                            if t == s || case.apply(t) != s {
+                                continue;
+                            }
+                            let substring_exprs = substring_set(ci, l, r);
+                            exprs.push(ConvertSet(*case, Box::new(substring_exprs)));
+                        }
+                    }
+                }
+                // learn all table lookups of substring expressions, for the keys that map to s
+                for table in tables {
+                    for key in table.keys_for(s).iter().filter(|key| !key.is_empty()) {
+                        for (ci, input_str) in input.iter().enumerate() {
+                            for (l, _) in input_str.match_indices(key.as_str()) {
+                                let r = l + key.len();
+                                let substring_exprs = substring_set(ci, l, r);
+                                exprs.push(LookupSet(
+                                    table.clone(),
+                                    Box::new(substring_exprs),
+                                    len,
+                                ));
+                            }
+                        }
+                    }
+                }
+                // learn all reformattings of numbers, if s looks like a number
+                let numeric = |b: Option<&u8>| b.is_some_and(u8::is_ascii_digit);
+                if numeric(s.as_bytes().first()) && numeric(s.as_bytes().last()) {
+                    for (ci, input_str) in input.iter().enumerate() {
+                        for (l, r) in number::find_numbers(input_str) {
+                            let formats: BTreeSet<NumberFormat> =
+                                NumberFormat::formats_for(&input_str[l..r], s)
+                                    .into_iter()
+                                    .collect();
+                            if formats.is_empty() {
+                                continue;
+                            }
+                            let substring_exprs = substring_set(ci, l, r);
+                            exprs.push(NumberSet(formats, Box::new(substring_exprs), len));
+                        }
+                    }
+                }
+                // learn all reformattings of dates, for the substrings of the input that look like
+                // dates
+                if dates.iter().any(|d| !d.is_empty()) && at_boundaries(i, j) {
+                    for (ci, candidates) in dates.iter().enumerate() {
+                        for (l, r, parses) in candidates {
+                            let pairs: BTreeSet<(DateFormat, DateFormat)> = parses
+                                .iter()
+                                .flat_map(|(input_format, date)| {
+                                    DateFormat::formats_for(date, s).into_iter().map(
+                                        move |output_format| (input_format.clone(), output_format),
+                                    )
+                                })
+                                .collect();
+                            if pairs.is_empty() {
+                                continue;
+                            }
+                            let substring_exprs = substring_set(ci, *l, *r);
+                            exprs.push(DateSet(pairs, Box::new(substring_exprs), len));
+                        }
+                    }
+                }
+                // learn all additions of a constant to integers in the input, if s is an integer
+                // that nothing else produces: no copy, number format, or date expression, because
+                // otherwise arithmetic mostly fits by coincidence, like a month that happens to be
+                // the day plus four; the integers in the input are all of the suffixes of the runs
+                // of digits, so that 1985 can become '86 by adding one to 85
+                let produced = copied
+                    || exprs
+                        .iter()
+                        .any(|e| matches!(e, NumberSet(..) | DateSet(..)));
+                let target = Some(s)
+                    .filter(|s| {
+                        !produced && s.bytes().all(|b| b.is_ascii_digit()) && at_boundaries(i, j)
+                    })
+                    .and_then(number::parse_integer);
+                if let Some(target) = target {
+                    for (ci, input_str) in input.iter().enumerate() {
+                        for (start, r) in number::find_integers(input_str) {
+                            for l in start..r {
+                                let k = number::parse_integer(&input_str[l..r])
+                                    .and_then(|n| target.checked_sub(n))
+                                    .filter(|k| *k != 0);
+                                let k = match k {
+                                    Some(k) => k,
+                                    None => continue,
+                                };
+                                let formats: BTreeSet<(i64, NumberFormat)> =
+                                    NumberFormat::formats_for(&target.to_string(), s)
+                                        .into_iter()
+                                        .map(|format| (k, format))
+                                        .collect();
+                                let substring_exprs = substring_set(ci, l, r);
+                                exprs.push(IntegerSet(formats, Box::new(substring_exprs), len));
                             }
                         }
                     }
                 }
+                // learn all loops
+                exprs.extend(loops.remove(&(i, j)).unwrap_or_default());
                 substrings.insert((i, j), exprs);
             }
         }
@@ -103,58 +374,247 @@ impl Dag {
         }
     }
 
-    pub fn learn(paired: &[(Vec<&str>, &str)], graph: &InputDataGraph) -> Self {
-        paired
+    pub fn learn(paired: &[(Vec<&str>, &str)], graph: &InputDataGraph, tables: &[Table]) -> Self {
+        let dag = |row: usize| {
+            let (input, output) = &paired[row];
+            Self::new(input, output, graph, row, tables)
+        };
+        let intersect = |d1: Self, d2: Self| d1.intersection(&d2);
+        par::map_reduce(0..paired.len(), &dag, &intersect).unwrap()
+    }
+
+    // checks whether there is any program that is consistent with all the examples that this dag
+    // was learned from
+    pub fn has_program(&self) -> bool {
+        let adj = graph::adjacency_map(self.substrings.keys());
+        graph::shortest_path_dag(&self.start, &self.finish, &adj, |_, _| 1).is_some()
+    }
+
+    // checks whether the given subset of the dags (which must be nonempty) has a program in common
+    fn consistent(dags: &[Self], subset: &[usize]) -> bool {
+        match subset {
+            [] => panic!("consistent: empty subset"),
+            [i] => dags[*i].has_program(),
+            [i, j, rest @ ..] => {
+                let mut acc = dags[*i].intersection(&dags[*j]);
+                for k in rest {
+                    if !acc.has_program() {
+                        return false;
+                    }
+                    acc = acc.intersection(&dags[*k]);
+                }
+                acc.has_program()
+            }
+        }
+    }
+
+    // finds a minimal set of examples that are inconsistent with each other, or returns None if
+    // all of the examples are consistent
+    pub fn find_conflict(
+        paired: &[(Vec<&str>, &str)],
+        graph: &InputDataGraph,
+        tables: &[Table],
+    ) -> Option<Conflict> {
+        let dags: Vec<Self> = paired
             .iter()
             .enumerate()
-            .map(|(row, (input, output))| Self::new(input, output, graph, row))
-            .fold(None, |acc, x| -> Option<Self> {
-                match acc {
-                    Some(acc) => Some(acc.intersection(&x)),
-                    None => Some(x),
+            .map(|(row, (input, output))| Self::new(input, output, graph, row, tables))
+            .collect();
+
+        // intersect incrementally to find the first example that is inconsistent with the ones
+        // before it
+        let mut acc: Option<Self> = None;
+        let mut first_bad = None;
+        for (i, dag) in dags.iter().enumerate().skip(1) {
+            let next = match &acc {
+                Some(acc) => acc.intersection(dag),
+                None => dags[0].intersection(dag),
+            };
+            if !next.has_program() {
+                first_bad = Some(i);
+                break;
+            }
+            acc = Some(next);
+        }
+        let first_bad = first_bad?;
+
+        // a single example is always consistent (it can be produced by a constant string), so the
+        // smallest possible conflict is a pair; these are cheap to check, and a mistyped example
+        // usually conflicts with some other example on its own
+        let mut examples = None;
+        'pairs: for j in 1..dags.len() {
+            for i in 0..j {
+                if !Self::consistent(&dags, &[i, j]) {
+                    examples = Some(vec![i, j]);
+                    break 'pairs;
                 }
+            }
+        }
+        // otherwise, shrink the inconsistent prefix by removing examples as long as the rest stay
+        // inconsistent; first_bad has to stay, because everything before it is consistent
+        let examples = examples.unwrap_or_else(|| {
+            let mut subset: Vec<usize> = (0..first_bad + 1).collect();
+            let mut i = 0;
+            while subset[i] != first_bad {
+                let without: Vec<usize> =
+                    subset.iter().cloned().filter(|k| *k != subset[i]).collect();
+                if Self::consistent(&dags, &without) {
+                    i += 1;
+                } else {
+                    subset = without;
+                }
+            }
+            subset
+        });
+
+        // removing an example outside of the conflict can't resolve it, so only the examples in
+        // the conflict are candidates for being the odd one out
+        let culprits = examples
+            .iter()
+            .cloned()
+            .filter(|i| {
+                let rest: Vec<usize> = (0..dags.len()).filter(|k| k != i).collect();
+                Self::consistent(&dags, &rest)
             })
-            .unwrap()
+            .collect();
+
+        Some(Conflict { examples, culprits })
     }
 
-    pub fn top_ranked_expression(&self, graph: &InputDataGraph) -> Option<StringExpression> {
-        let distances = graph.distances();
-        let ranks = graph.rank_nodes(&distances);
-        let mut best_by_edge: BTreeMap<Edge, (usize, SubstringExpression)> = BTreeMap::new();
-        // compute distances for edges
-        let idg_adj = graph::adjacency_map(graph.edges());
-        let idg_inv = graph::invert_adjacency_map(&idg_adj);
+    pub fn top_ranked_expression(
+        &self,
+        graph: &InputDataGraph,
+        ranking: Ranking,
+    ) -> Option<StringExpression> {
+        self.top_k_expressions(graph, 1, ranking)
+            .pop()
+            .map(|(_, expr)| expr)
+    }
+
+    // finds the k highest-ranked expressions, along with their scores, in order of decreasing
+    // score; expressions are distinct, because each one comes from a different path through the
+    // dag, and a substring expression determines which part of the first output it produces
+    pub fn top_k_expressions(
+        &self,
+        graph: &InputDataGraph,
+        k: usize,
+        ranking: Ranking,
+    ) -> Vec<(usize, StringExpression)> {
+        let ranker = Ranker::new(graph, ranking);
+        let shortest_paths = |alternatives_by_edge: &BTreeMap<Edge, Vec<(usize, _)>>| {
+            let adj = graph::adjacency_map(alternatives_by_edge.keys());
+            graph::k_shortest_paths_dag(&self.start, &self.finish, &adj, k, |v1, v2| {
+                // negating because graph finds lowest cost path, we want highest score
+                alternatives_by_edge[&(*v1, *v2)]
+                    .iter()
+                    .map(|(score, _)| -(*score as isize))
+                    .collect()
+            })
+        };
+        // leave out the expressions that are less likely to generalize, unless that leaves no path
+        // through the dag; every path is consistent with the examples, so there is always one
+        // without leaving anything out
+        let mut alternatives_by_edge = self.alternatives_by_edge(&ranker, k, false);
+        let mut paths = shortest_paths(&alternatives_by_edge);
+        if paths.is_empty() {
+            alternatives_by_edge = self.alternatives_by_edge(&ranker, k, true);
+            paths = shortest_paths(&alternatives_by_edge);
+        }
+        paths
+            .into_iter()
+            .map(|(cost, path)| {
+                let expr = StringExpression(
+                    path.iter()
+                        .map(|(e, alt)| alternatives_by_edge[e][*alt].1.clone())
+                        .collect(),
+                );
+                (-cost as usize, expr)
+            })
+            .collect()
+    }
+
+    // finds the k highest-scoring expressions for each edge, along with their scores, leaving out
+    // the edges that have none; substrings that fail on some of the inputs and loops over a single
+    // match are only kept if lenient
+    fn alternatives_by_edge(
+        &self,
+        ranker: &Ranker,
+        k: usize,
+        lenient: bool,
+    ) -> BTreeMap<Edge, Vec<(usize, SubstringExpression)>> {
+        let Ranker {
+            graph,
+            ranking,
+            lengths,
+            ranks,
+            idg_adj,
+            idg_inv,
+        } = ranker;
+        let mut alternatives_by_edge: BTreeMap<Edge, Vec<(usize, SubstringExpression)>> =
+            BTreeMap::new();
         for (edge, expr_set_set) in &self.substrings {
-            let mut best: Option<SubstringExpression> = None;
-            let mut best_score = 0;
+            let mut alternatives: Vec<(usize, SubstringExpression)> = Vec::new();
             for expr_set in expr_set_set {
+                // an offset that only one example shows is as likely to be a coincidence as not,
+                // so arithmetic needs the same offset to hold across at least two examples
+                if matches!(expr_set, IntegerSet(..)) && self.num_examples < 2 {
+                    continue;
+                }
+                // case conversions are ranked like the expressions they convert, and lookups and
+                // number and date formatting are ranked by the length of their output, because it
+                // can be very different from the length of their input
+                let (wrapper, expr_set) = match expr_set {
+                    ConvertSet(case, e) => (Some(Wrapper::Convert(*case)), &**e),
+                    LookupSet(table, e, len) => (Some(Wrapper::Lookup(table, *len)), &**e),
+                    NumberSet(formats, e, len) => (Some(Wrapper::Number(formats, *len)), &**e),
+                    DateSet(pairs, e, len) => (Some(Wrapper::Date(pairs, *len)), &**e),
+                    IntegerSet(sums, e, len) => (Some(Wrapper::Integer(sums, *len)), &**e),
+                    NestedSet(e, p_l, p_r, len) => (Some(Wrapper::Nested(p_l, p_r, *len)), &**e),
+                    e => (None, e),
+                };
                 let expr;
                 let score;
                 match expr_set {
                     ConstantString(s) => {
                         expr = Some(SubstringExpression::ConstantString(s.clone()));
-                        score = s.len() * s.len() * EPSILON;
+                        let len = s.chars().count();
+                        score = len * len * EPSILON;
                     }
                     SubstringSet(ci, p_l, p_r) => {
                         let key = |p: &'_ &PositionSet| -> usize {
                             match p {
-                                ConstantPosition(_) => 0,
+                                // boundaries come after constant positions in the set, so they
+                                // win ties with them
+                                ConstantPosition(_) | Boundary(_, _, _) => 0,
                                 GraphNode(v) => ranks[v],
                             }
                         };
-                        let p_l = p_l.iter().max_by_key(key).unwrap();
-                        let p_r = p_r.iter().max_by_key(key).unwrap();
+                        let by_rank = || {
+                            (
+                                p_l.iter().max_by_key(key).unwrap(),
+                                p_r.iter().max_by_key(key).unwrap(),
+                            )
+                        };
 
                         // NOTE all of the programs captured in the dag are consistent with the
                         // input-output examples, but they aren't necessarily even valid for the
-                        // rest of the inputs.
-                        //
-                        // XXX Maybe we should find all the consistent (p_l, p_r) pairs first
-                        // instead of taking the max by key above, and then after that take the max
-                        // by key, so we don't accidentally throw away a good example and then be
-                        // left with one that doesn't work. The BlinkFill paper isn't very clear
-                        // about avoiding generating invalid programs for the input strings that
-                        // don't have output examples.
+                        // rest of the inputs. The BlinkFill paper isn't very clear about avoiding
+                        // generating invalid programs for the input strings that don't have output
+                        // examples, so by default, we take the max by key above, which might
+                        // throw away a good pair of positions and be left with one that doesn't
+                        // work. With validated ranking, we find all the (p_l, p_r) pairs that work
+                        // on every input first, and only then take the max by key; if there are
+                        // none, we fall back to the max by key. Validity only decides between
+                        // positions, so it never changes how the expression ranks against others.
+                        let valid = match ranking {
+                            Ranking::Paper => None,
+                            Ranking::Validated => p_l
+                                .iter()
+                                .flat_map(|l| p_r.iter().map(move |r| (l, r)))
+                                .filter(|(l, r)| evaluates_everywhere(*ci, l, r, graph, lengths))
+                                .max_by_key(|(l, r)| key(l) + key(r)),
+                        };
+                        let (p_l, p_r) = valid.unwrap_or_else(by_rank);
 
                         let sample = |p: &PositionSet| -> Position {
                             match p {
@@ -167,17 +627,15 @@ impl Dag {
                                     // tokens, and then matches near the start or end
                                     if let Some(vss) = idg_inv.get(v) {
                                         for vs in vss {
-                                            if let Some(toks) = graph.tokens.get(&(*vs, *v)) {
-                                                for (tok, occ) in toks {
-                                                    let weight = (tok.weight(), occ.weight());
-                                                    if best.is_none() || weight > best_weight {
-                                                        best_weight = weight;
-                                                        best = Some(Position::Match(
-                                                            tok.clone(),
-                                                            *occ,
-                                                            Direction::End,
-                                                        ));
-                                                    }
+                                            for (tok, occ) in graph.edge_tokens(&(*vs, *v)) {
+                                                let weight = (tok.weight(), occ.weight());
+                                                if best.is_none() || weight > best_weight {
+                                                    best_weight = weight;
+                                                    best = Some(Position::Match(
+                                                        tok.clone(),
+                                                        occ,
+                                                        Direction::End,
+                                                    ));
                                                 }
                                             }
                                         }
@@ -185,17 +643,15 @@ impl Dag {
                                     // check out-edges
                                     if let Some(vfs) = idg_adj.get(v) {
                                         for vf in vfs {
-                                            if let Some(toks) = graph.tokens.get(&(*v, *vf)) {
-                                                for (tok, occ) in toks {
-                                                    let weight = (tok.weight(), occ.weight());
-                                                    if best.is_none() || weight > best_weight {
-                                                        best_weight = weight;
-                                                        best = Some(Position::Match(
-                                                            tok.clone(),
-                                                            *occ,
-                                                            Direction::Start,
-                                                        ));
-                                                    }
+                                            for (tok, occ) in graph.edge_tokens(&(*v, *vf)) {
+                                                let weight = (tok.weight(), occ.weight());
+                                                if best.is_none() || weight > best_weight {
+                                                    best_weight = weight;
+                                                    best = Some(Position::Match(
+                                                        tok.clone(),
+                                                        occ,
+                                                        Direction::Start,
+                                                    ));
                                                 }
                                             }
                                         }
@@ -204,71 +660,35 @@ impl Dag {
                                     // PositionSet was invalid
                                     best.expect("top_ranked_expression: no tokens for graph node")
                                 }
+                                Boundary(before, after, k) => {
+                                    Position::Boundary(before.clone(), after.clone(), *k)
+                                }
                             }
                         };
 
-                        let len: usize;
-                        let mut bad = false;
-                        match (p_l, p_r) {
-                            (ConstantPosition(k1), ConstantPosition(k2)) => {
-                                len = k2.0 as usize - k1.0 as usize;
-                            }
-                            (ConstantPosition(k), GraphNode(v)) => {
-                                let k = k.0 as usize;
-                                let mut sum = 0;
-                                for (id, si) in &graph.labels[v] {
-                                    if si.0 > k {
-                                        // NOTE the paper isn't super clear about how to measure
-                                        // the length of token-based matches (it depends on which
-                                        // string we are matching); we could do it based on all the
-                                        // strings (including ones we don't have input-output
-                                        // examples for), or we could do it just for the ones
-                                        // included in the examples; doing the former might make more
-                                        // sense, so that is what we do here
-                                        if id.row < self.num_examples {
-                                            sum += si.0 - k;
-                                        }
-                                    } else {
-                                        bad = true;
-                                        break;
-                                    }
-                                }
-                                len = sum / self.num_examples;
-                            }
-                            (GraphNode(v), ConstantPosition(k)) => {
-                                // similar to the above case
-                                // note: difference direction is opposite the above case
-                                let k = k.0 as usize;
-                                let mut sum = 0;
-                                for (id, si) in &graph.labels[v] {
-                                    if k > si.0 {
-                                        if id.row < self.num_examples {
-                                            sum += k - si.0;
-                                        }
-                                    } else {
-                                        bad = true;
-                                        break;
-                                    }
-                                }
-                                len = sum / self.num_examples;
+                        // graph nodes have labels in all of the inputs, so positions that use them
+                        // are checked against all of the inputs; boundaries and constant positions
+                        // work in the examples by construction, and aren't checked
+                        let checked = match (p_l, p_r) {
+                            (Boundary(_, _, _), _) | (_, Boundary(_, _, _)) => false,
+                            (GraphNode(_), _) | (_, GraphNode(_)) => !lenient,
+                            _ => false,
+                        };
+                        let works = !checked || evaluates_everywhere(*ci, p_l, p_r, graph, lengths);
+                        // the length of the substring, averaged over the examples
+                        let mut sum = 0;
+                        for (id, n) in lengths {
+                            if id.col != ci.0 || id.row >= self.num_examples {
+                                continue;
                             }
-                            (GraphNode(v1), GraphNode(v2)) => {
-                                let mut sum = 0;
-                                for (id, si1) in &graph.labels[v1] {
-                                    let si2 = graph.labels[v2][id];
-                                    if si2.0 > si1.0 {
-                                        if id.row < self.num_examples {
-                                            sum += si2.0 - si1.0;
-                                        }
-                                    } else {
-                                        bad = true;
-                                        break;
-                                    }
-                                }
-                                len = sum / self.num_examples;
+                            if let (Some(l), Some(r)) =
+                                (p_l.index(id, *n, graph), p_r.index(id, *n, graph))
+                            {
+                                sum += r.saturating_sub(l);
                             }
                         }
-                        expr = if !bad {
+                        let len = sum / self.num_examples;
+                        expr = if works {
                             Some(SubstringExpression::Substring(
                                 *ci,
                                 sample(p_l),
@@ -279,46 +699,457 @@ impl Dag {
                         };
                         score = len * len * KAPPA;
                     }
+                    // a loop over a single match in every example is no different from a
+                    // substring in the examples, so it isn't worth the risk of repeating on other
+                    // inputs; it is only used as the substring of the match, if nothing else works
+                    LoopSet(_, _, None, _, _) if !lenient => {
+                        expr = None;
+                        score = 0;
+                    }
+                    LoopSet(ci, token, None, bodies, len) => {
+                        let (case, p_l, p_r) = bodies
+                            .iter()
+                            .min_by_key(|(case, p_l, p_r)| {
+                                (
+                                    case.is_some(),
+                                    body_position_key(p_l) + body_position_key(p_r),
+                                )
+                            })
+                            .unwrap();
+                        let first = SubstringExpression::Substring(
+                            *ci,
+                            Position::Match(token.clone(), Occurrence(1), Direction::Start),
+                            Position::Match(token.clone(), Occurrence(1), Direction::End),
+                        );
+                        let nested = SubstringExpression::SubstringOf(
+                            Box::new(first),
+                            p_l.clone(),
+                            p_r.clone(),
+                        );
+                        expr = Some(match case {
+                            Some(case) => SubstringExpression::Convert(*case, Box::new(nested)),
+                            None => nested,
+                        });
+                        let len = len / self.num_examples;
+                        let penalty =
+                            NESTED_PENALTY + if case.is_some() { CONVERT_PENALTY } else { 0 };
+                        score = (len * len * KAPPA).saturating_sub(penalty);
+                    }
+                    LoopSet(ci, token, Some(sep), bodies, len) => {
+                        // prefer bodies without case conversions, and then bodies with positions
+                        // close to the ends of the match, which generalize to matches of any
+                        // length; the body doesn't need to be validated, because it works on all
+                        // of the matches in the examples, and loops can't fail otherwise
+                        let (case, p_l, p_r) = bodies
+                            .iter()
+                            .min_by_key(|(case, p_l, p_r)| {
+                                (
+                                    case.is_some(),
+                                    body_position_key(p_l) + body_position_key(p_r),
+                                )
+                            })
+                            .unwrap();
+                        let body = loop_body_expression(*case, p_l, p_r);
+                        expr = Some(SubstringExpression::Loop(
+                            *ci,
+                            token.clone(),
+                            Box::new(body),
+                            sep.clone(),
+                        ));
+                        let len = len / self.num_examples;
+                        let penalty =
+                            LOOP_PENALTY + if case.is_some() { CONVERT_PENALTY } else { 0 };
+                        score = (len * len * KAPPA).saturating_sub(penalty);
+                    }
+                    ConvertSet(_, _)
+                    | LookupSet(_, _, _)
+                    | NumberSet(_, _, _)
+                    | DateSet(_, _, _)
+                    | IntegerSet(_, _, _)
+                    | NestedSet(_, _, _, _) => {
+                        unreachable!("nested conversion")
+                    }
                 }
+                let output_score = |len: usize, penalty: usize| {
+                    let len = len / self.num_examples;
+                    (len * len * KAPPA).saturating_sub(penalty)
+                };
+                let (expr, score) = match wrapper {
+                    Some(Wrapper::Convert(case)) => (
+                        expr.map(|e| SubstringExpression::Convert(case, Box::new(e))),
+                        score.saturating_sub(CONVERT_PENALTY),
+                    ),
+                    Some(Wrapper::Lookup(table, len)) => (
+                        expr.map(|e| SubstringExpression::Lookup(table.clone(), Box::new(e))),
+                        output_score(len, LOOKUP_PENALTY),
+                    ),
+                    Some(Wrapper::Number(formats, len)) => {
+                        // prefer formats that change the number less, which also generalize
+                        // better: no padding, keeping the decimals, and no separator
+                        let format = formats
+                            .iter()
+                            .min_by_key(|f| (f.width, f.decimals.is_some(), f.separator.is_some()))
+                            .unwrap();
+                        (
+                            expr.map(|e| SubstringExpression::Number(format.clone(), Box::new(e))),
+                            output_score(len, NUMBER_PENALTY),
+                        )
+                    }
+                    Some(Wrapper::Date(pairs, len)) => {
+                        // a date like 03/07/2021 can be day-first or month-first, but only one of
+                        // them can parse 25/12/2021, so the unpaired rows tell them apart; prefer
+                        // the input format that parses the date in the most rows
+                        let mut counts: BTreeMap<&DateFormat, usize> = BTreeMap::new();
+                        let mut parsed = |format| {
+                            *counts.entry(format).or_insert_with(|| match &expr {
+                                Some(e) => graph
+                                    .rows
+                                    .iter()
+                                    .filter_map(|row| e.run(row))
+                                    .filter(|s| format.parse(s).is_some())
+                                    .count(),
+                                None => 0,
+                            })
+                        };
+                        let (input, output) = pairs
+                            .iter()
+                            .min_by_key(|(input, _)| Reverse(parsed(input)))
+                            .unwrap();
+                        (
+                            expr.map(|e| {
+                                SubstringExpression::Date(
+                                    input.clone(),
+                                    output.clone(),
+                                    Box::new(e),
+                                )
+                            }),
+                            output_score(len, DATE_PENALTY),
+                        )
+                    }
+                    Some(Wrapper::Integer(sums, len)) => {
+                        // prefer smaller constants, and then formats that keep the number of
+                        // digits in the examples, so that 85 + 1 is written as 86 and 03 + 1 as 04
+                        let (k, format) = sums
+                            .iter()
+                            .min_by_key(|(k, f)| {
+                                (
+                                    k.unsigned_abs(),
+                                    Reverse(f.width),
+                                    f.decimals.is_some(),
+                                    f.separator.is_some(),
+                                )
+                            })
+                            .unwrap();
+                        let penalty = ARITHMETIC_PENALTY + k.unsigned_abs().to_string().len();
+                        (
+                            expr.map(|e| {
+                                let parsed = IntegerExpression::Parse(Box::new(e));
+                                let sum = IntegerExpression::Add(Box::new(parsed), *k);
+                                SubstringExpression::Integer(sum, format.clone())
+                            }),
+                            output_score(len, penalty),
+                        )
+                    }
+                    Some(Wrapper::Nested(p_l, p_r, len)) => {
+                        // the positions are in the intermediate strings, so they can't be checked
+                        // against the input data graph; prefer positions that work on the
+                        // intermediate strings of all of the rows, and then the ones that
+                        // generalize best
+                        let sources: Vec<String> = match &expr {
+                            Some(e) => graph.rows.iter().filter_map(|row| e.run(row)).collect(),
+                            None => Vec::new(),
+                        };
+                        let best = |positions: &BTreeSet<Position>| -> Position {
+                            positions
+                                .iter()
+                                .max_by_key(|p| {
+                                    let works = sources.iter().all(|t| p.run(t).is_some());
+                                    (works, nested_position_key(p))
+                                })
+                                .unwrap()
+                                .clone()
+                        };
+                        let (p_l, p_r) = (best(p_l), best(p_r));
+                        (
+                            expr.map(|e| SubstringExpression::SubstringOf(Box::new(e), p_l, p_r)),
+                            output_score(len, NESTED_PENALTY),
+                        )
+                    }
+                    None => (expr, score),
+                };
                 if let Some(expr) = expr {
-                    if best.is_none() || score > best_score {
-                        best = Some(expr);
-                        best_score = score;
+                    alternatives.push((score, expr));
+                }
+            }
+            // the sort is stable, so among expressions with the same score, the one that comes
+            // first wins, and duplicates are removed keeping their best score
+            alternatives.sort_by_key(|(score, _)| Reverse(*score));
+            let mut seen = BTreeSet::new();
+            alternatives.retain(|(_, expr)| seen.insert(expr.clone()));
+            alternatives.truncate(k);
+            if !alternatives.is_empty() {
+                alternatives_by_edge.insert(*edge, alternatives);
+            }
+        }
+        alternatives_by_edge
+    }
+}
+
+// an expression that is applied to the output of a substring expression, for ranking
+enum Wrapper<'a> {
+    Convert(Case),
+    Lookup(&'a Table, usize),
+    Number(&'a BTreeSet<NumberFormat>, usize),
+    Date(&'a BTreeSet<(DateFormat, DateFormat)>, usize),
+    Integer(&'a BTreeSet<(i64, NumberFormat)>, usize),
+    Nested(&'a BTreeSet<Position>, &'a BTreeSet<Position>, usize),
+}
+
+// checks whether a substring between the given positions evaluates successfully on every string in
+// the column; the tokens on the graph's edges match in every string by construction, so only the
+// positions need to be checked
+fn evaluates_everywhere(
+    ci: ColumnIndex,
+    p_l: &PositionSet,
+    p_r: &PositionSet,
+    graph: &InputDataGraph,
+    lengths: &BTreeMap<Id, usize>,
+) -> bool {
+    lengths
+        .iter()
+        .filter(|(id, _)| id.col == ci.0)
+        .all(
+            |(id, len)| match (p_l.index(id, *len, graph), p_r.index(id, *len, graph)) {
+                (Some(l), Some(r)) => l < r,
+                _ => false,
+            },
+        )
+}
+
+// generates the loops that produce substrings of the output, by edge of the output; a loop body is
+// consistent if running it on every match of the token and joining the results with some separator
+// produces the substring, so we only need to try the bodies that produce a prefix of the substring
+// from the first match, and the separator is then determined by the length of the substring
+fn generate_loop_sets(input: &[&str], output: &str) -> BTreeMap<Edge, Vec<SubstringExpressionSet>> {
+    type LoopKey = (Edge, ColumnIndex, Token, Option<String>);
+    let mut sets: BTreeMap<LoopKey, BTreeSet<LoopBody>> = BTreeMap::new();
+    for (ci, input_str) in input.iter().enumerate() {
+        for token in LOOP_TOKENS {
+            let matches: Vec<&str> = token
+                .all_matches(input_str)
+                .iter()
+                .filter_map(|m| slice_chars(input_str, m.start, m.end))
+                .collect();
+            if matches.is_empty() {
+                continue;
+            }
+            for i in (0..output.len()).filter(|i| output.is_char_boundary(*i)) {
+                let rest = &output[i..];
+                for body in loop_bodies(matches[0], rest) {
+                    let body_expr = loop_body_expression(body.0, &body.1, &body.2);
+                    let parts: Option<Vec<String>> =
+                        matches.iter().map(|m| body_expr.run(&[m])).collect();
+                    let parts = match parts {
+                        Some(parts) => parts,
+                        None => continue,
+                    };
+                    let mut add = |len: usize, sep: Option<String>| {
+                        let key = ((i, i + len), ColumnIndex(ci), token.clone(), sep);
+                        sets.entry(key).or_default().insert(body.clone());
+                    };
+                    if parts.len() == 1 {
+                        // with a single match, any separator works
+                        add(parts[0].len(), None);
+                        continue;
+                    }
+                    let total: usize = parts.iter().map(String::len).sum();
+                    let gaps = parts.len() - 1;
+                    let first = parts[0].len();
+                    let mut sep_len = 0;
+                    while total + sep_len * gaps <= rest.len() {
+                        let sep = rest.get(first..first + sep_len);
+                        // separators are punctuation or whitespace; letters and digits in between
+                        // the parts are much more likely to be a coincidence than a separator
+                        if let Some(sep) = sep.filter(|sep| !sep.chars().any(char::is_alphanumeric))
+                        {
+                            let joined = parts.join(sep);
+                            if rest.starts_with(&joined) {
+                                add(joined.len(), Some(String::from(sep)));
+                            }
+                        }
+                        sep_len += 1;
                     }
                 }
             }
-            if let Some(best) = best {
-                best_by_edge.insert(*edge, (best_score, best));
+        }
+    }
+    // the edges are byte offsets in the output, but the nodes of the DAG count characters
+    let offsets = char_offsets(output);
+    let mut loops: BTreeMap<Edge, Vec<SubstringExpressionSet>> = BTreeMap::new();
+    for (((l, r), ci, token, sep), bodies) in sets {
+        let edge = (offsets[l], offsets[r]);
+        let len = edge.1 - edge.0;
+        loops
+            .entry(edge)
+            .or_default()
+            .push(LoopSet(ci, token, sep, bodies, len));
+    }
+    loops
+}
+
+// finds the loop bodies that produce a prefix of the given string from the given match
+fn loop_bodies(m: &str, s: &str) -> Vec<LoopBody> {
+    // the byte offsets of the characters of the match, and of its end
+    let boundaries: Vec<usize> = m
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(iter::once(m.len()))
+        .collect();
+    let n = boundaries.len() as isize - 1;
+    let mut bodies = Vec::new();
+    for (a, start) in boundaries.iter().enumerate() {
+        for (b, end) in boundaries.iter().enumerate().skip(a + 1) {
+            let t = &m[*start..*end];
+            let cases = Some(None).into_iter().chain(CASES.iter().map(|c| Some(*c)));
+            for case in cases {
+                let matched = match case {
+                    None => s.starts_with(t),
+                    // as with substrings, skip conversions that don't change anything
+                    Some(case) => {
+                        let converted = case.apply(t);
+                        converted != t && s.starts_with(&converted)
+                    }
+                };
+                if !matched {
+                    continue;
+                }
+                let (a, b) = (a as isize, b as isize);
+                // positions can count from either end of the match
+                let mut starts = vec![Position::ConstantPosition(Occurrence(a + 1))];
+                starts.push(Position::ConstantPosition(Occurrence(a - n)));
+                let mut ends = vec![Position::ConstantPosition(Occurrence(b + 1))];
+                if b < n {
+                    ends.push(Position::ConstantPosition(Occurrence(b - n)));
+                } else {
+                    ends.push(Position::Match(Token::End, Occurrence(1), Direction::Start));
+                }
+                for p_l in &starts {
+                    for p_r in &ends {
+                        bodies.push((case, p_l.clone(), p_r.clone()));
+                    }
+                }
+            }
+        }
+    }
+    bodies
+}
+
+// builds the expression for a loop body, which runs on the match as a single column
+fn loop_body_expression(case: Option<Case>, p_l: &Position, p_r: &Position) -> SubstringExpression {
+    let e = SubstringExpression::Substring(ColumnIndex(0), p_l.clone(), p_r.clone());
+    match case {
+        Some(case) => SubstringExpression::Convert(case, Box::new(e)),
+        None => e,
+    }
+}
+
+// finds the intermediate strings for nested substrings, which are the parts of the input between
+// punctuation (or the ends of the input), such as the part between parentheses, other than the
+// whole input; each part ends at one of the next few punctuation characters after its start, so
+// that the number of them is linear in the length of the input
+fn intermediate_spans(s: &str) -> Vec<(usize, usize)> {
+    const MAX_SPANNED: usize = 3;
+    let mut delimiters = vec![(0, 0)];
+    delimiters.extend(
+        s.char_indices()
+            .filter(|(_, c)| !c.is_alphanumeric() && !c.is_whitespace())
+            .map(|(i, c)| (i, i + c.len_utf8())),
+    );
+    delimiters.push((s.len(), s.len()));
+    let mut spans = Vec::new();
+    for (k, (_, a)) in delimiters.iter().enumerate() {
+        for (b, _) in delimiters[k + 1..].iter().take(MAX_SPANNED) {
+            if a < b && (*a, *b) != (0, s.len()) {
+                spans.push((*a, *b));
             }
         }
-        // find shortest path
-        let adj = graph::adjacency_map(best_by_edge.keys());
-        let path = graph::shortest_path_dag(&self.start, &self.finish, &adj, |v1, v2| {
-            // negating because graph finds lowest cost path, we want highest score
-            -(best_by_edge[&(*v1, *v2)].0 as isize)
-        })?;
-        Some(StringExpression(
-            path.iter()
-                .map(|e| best_by_edge.remove(e).unwrap().1)
-                .collect(),
-        ))
+    }
+    spans
+}
+
+// positions in intermediate strings are ranked by kind, with token matches first and constant
+// positions last, and then like the positions of graph nodes
+fn nested_position_key(p: &Position) -> (usize, usize, isize) {
+    match p {
+        Position::Match(token, k, _) => (2, token.weight(), k.weight()),
+        Position::Boundary(before, after, k) => {
+            (1, cmp::min(before.weight(), after.weight()), k.weight())
+        }
+        _ => (0, 0, p.occurrence().weight()),
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+// positions in loop bodies are ranked by how far they are from the ends of the match
+fn body_position_key(p: &Position) -> isize {
+    match p {
+        Position::ConstantPosition(k) => k.0.abs(),
+        // the end of the match
+        _ => 1,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 enum SubstringExpressionSet {
     ConstantString(String),
     SubstringSet(ColumnIndex, BTreeSet<PositionSet>, BTreeSet<PositionSet>),
+    ConvertSet(Case, Box<SubstringExpressionSet>),
+    // the last field is the total length of the output of the lookup in the examples, for ranking
+    LookupSet(Table, Box<SubstringExpressionSet>, usize),
+    // like LookupSet, with the formats that produce the output from the number in the examples
+    NumberSet(BTreeSet<NumberFormat>, Box<SubstringExpressionSet>, usize),
+    // like LookupSet, with the pairs of input and output formats that produce the output from the
+    // date in the examples
+    DateSet(
+        BTreeSet<(DateFormat, DateFormat)>,
+        Box<SubstringExpressionSet>,
+        usize,
+    ),
+    // like NumberSet, with the constants that are added to the integer before formatting it; these
+    // are all the same unless the input has several integers in the same place
+    IntegerSet(
+        BTreeSet<(i64, NumberFormat)>,
+        Box<SubstringExpressionSet>,
+        usize,
+    ),
+    // the substrings of the intermediate strings produced by a SubstringSet, between positions in
+    // them; like LookupSet, the last field is the total length of the output in the examples
+    NestedSet(
+        Box<SubstringExpressionSet>,
+        BTreeSet<Position>,
+        BTreeSet<Position>,
+        usize,
+    ),
+    // the separator is None if it is unconstrained, because every example has a single match; the
+    // last field is the total length of the output of the loop in the examples, for ranking
+    LoopSet(
+        ColumnIndex,
+        Token,
+        Option<String>,
+        BTreeSet<LoopBody>,
+        usize,
+    ),
 }
 
 use SubstringExpressionSet::*;
 
 impl SubstringExpressionSet {
-    // returns a SubstringSet
+    // returns a SubstringSet; the boundaries are the ones in the string with the given id, from
+    // InputDataGraph::boundaries
     fn generate_substring_set(
         id: Id,
         l: StringIndex,
         r: StringIndex,
+        boundaries: &BTreeMap<usize, BTreeSet<(Token, Token, Occurrence)>>,
         graph: &InputDataGraph,
     ) -> Self {
         let mut v_l = BTreeSet::new();
@@ -332,6 +1163,11 @@ impl SubstringExpressionSet {
         }
         v_l.insert(ConstantPosition(Occurrence(l.0 as isize)));
         v_r.insert(ConstantPosition(Occurrence(r.0 as isize)));
+        for (index, v) in [(l, &mut v_l), (r, &mut v_r)] {
+            for (before, after, k) in boundaries.get(&index.0).into_iter().flatten() {
+                v.insert(Boundary(before.clone(), after.clone(), *k));
+            }
+        }
         SubstringSet(ColumnIndex(id.col), v_l, v_r)
     }
 
@@ -353,6 +1189,71 @@ impl SubstringExpressionSet {
                     }
                 }
             }
+            ConvertSet(case, e) => {
+                for e in e.denote(graph) {
+                    set.insert(SubstringExpression::Convert(*case, Box::new(e)));
+                }
+            }
+            LookupSet(table, e, _) => {
+                for e in e.denote(graph) {
+                    set.insert(SubstringExpression::Lookup(table.clone(), Box::new(e)));
+                }
+            }
+            NumberSet(formats, e, _) => {
+                for e in e.denote(graph) {
+                    for format in formats {
+                        set.insert(SubstringExpression::Number(
+                            format.clone(),
+                            Box::new(e.clone()),
+                        ));
+                    }
+                }
+            }
+            DateSet(pairs, e, _) => {
+                for e in e.denote(graph) {
+                    for (input, output) in pairs {
+                        set.insert(SubstringExpression::Date(
+                            input.clone(),
+                            output.clone(),
+                            Box::new(e.clone()),
+                        ));
+                    }
+                }
+            }
+            IntegerSet(sums, e, _) => {
+                for e in e.denote(graph) {
+                    for (k, format) in sums {
+                        let parsed = IntegerExpression::Parse(Box::new(e.clone()));
+                        set.insert(SubstringExpression::Integer(
+                            IntegerExpression::Add(Box::new(parsed), *k),
+                            format.clone(),
+                        ));
+                    }
+                }
+            }
+            NestedSet(e, p_l, p_r, _) => {
+                for e in e.denote(graph) {
+                    for p_l in p_l {
+                        for p_r in p_r {
+                            set.insert(SubstringExpression::SubstringOf(
+                                Box::new(e.clone()),
+                                p_l.clone(),
+                                p_r.clone(),
+                            ));
+                        }
+                    }
+                }
+            }
+            LoopSet(ci, token, sep, bodies, _) => {
+                for (case, p_l, p_r) in bodies {
+                    set.insert(SubstringExpression::Loop(
+                        *ci,
+                        token.clone(),
+                        Box::new(loop_body_expression(*case, p_l, p_r)),
+                        sep.clone().unwrap_or_default(),
+                    ));
+                }
+            }
         }
         set
     }
@@ -375,6 +1276,62 @@ impl SubstringExpressionSet {
                 }
                 Some(SubstringSet(*c1, p_l, p_r))
             }
+            (ConvertSet(case1, e1), ConvertSet(case2, e2)) if case1 == case2 => {
+                e1.intersection(e2).map(|e| ConvertSet(*case1, Box::new(e)))
+            }
+            (LookupSet(table1, e1, len1), LookupSet(table2, e2, len2)) if table1 == table2 => e1
+                .intersection(e2)
+                .map(|e| LookupSet(table1.clone(), Box::new(e), len1 + len2)),
+            (NumberSet(f1, e1, len1), NumberSet(f2, e2, len2)) => {
+                let formats: BTreeSet<_> = f1.intersection(f2).cloned().collect();
+                if formats.is_empty() {
+                    return None;
+                }
+                e1.intersection(e2)
+                    .map(|e| NumberSet(formats, Box::new(e), len1 + len2))
+            }
+            (DateSet(p1, e1, len1), DateSet(p2, e2, len2)) => {
+                let pairs: BTreeSet<_> = p1.intersection(p2).cloned().collect();
+                if pairs.is_empty() {
+                    return None;
+                }
+                e1.intersection(e2)
+                    .map(|e| DateSet(pairs, Box::new(e), len1 + len2))
+            }
+            (IntegerSet(s1, e1, len1), IntegerSet(s2, e2, len2)) => {
+                let sums: BTreeSet<_> = s1.intersection(s2).cloned().collect();
+                if sums.is_empty() {
+                    return None;
+                }
+                e1.intersection(e2)
+                    .map(|e| IntegerSet(sums, Box::new(e), len1 + len2))
+            }
+            (NestedSet(e1, l1, r1, len1), NestedSet(e2, l2, r2, len2)) => {
+                let p_l: BTreeSet<_> = l1.intersection(l2).cloned().collect();
+                if p_l.is_empty() {
+                    return None;
+                }
+                let p_r: BTreeSet<_> = r1.intersection(r2).cloned().collect();
+                if p_r.is_empty() {
+                    return None;
+                }
+                e1.intersection(e2)
+                    .map(|e| NestedSet(Box::new(e), p_l, p_r, len1 + len2))
+            }
+            (LoopSet(c1, t1, sep1, b1, len1), LoopSet(c2, t2, sep2, b2, len2))
+                if c1 == c2 && t1 == t2 =>
+            {
+                let sep = match (sep1, sep2) {
+                    (Some(s1), Some(s2)) if s1 != s2 => return None,
+                    (Some(s), _) | (_, Some(s)) => Some(s.clone()),
+                    (None, None) => None,
+                };
+                let bodies: BTreeSet<_> = b1.intersection(b2).cloned().collect();
+                if bodies.is_empty() {
+                    return None;
+                }
+                Some(LoopSet(*c1, t1.clone(), sep, bodies, len1 + len2))
+            }
             _ => None,
         }
     }
@@ -384,11 +1341,38 @@ impl SubstringExpressionSet {
 enum PositionSet {
     ConstantPosition(Occurrence),
     GraphNode(Node),
+    // unlike graph nodes, boundaries are found in each example separately, so they work even
+    // where the tokens on either side have different occurrences in different strings
+    Boundary(Token, Token, Occurrence),
 }
 
 use PositionSet::*;
 
 impl PositionSet {
+    // computes the index that this position evaluates to in the string with the given id, which
+    // has the given length
+    fn index(&self, id: &Id, len: usize, graph: &InputDataGraph) -> Option<usize> {
+        match self {
+            ConstantPosition(k) => {
+                // same as Position::run
+                let n = len as isize;
+                let k = if k.0 > 0 { k.0 } else { n + k.0 + 1 };
+                if 0 < k && k <= n + 1 {
+                    Some(k as usize)
+                } else {
+                    None
+                }
+            }
+            GraphNode(v) => graph.labels[v].get(id).map(|si| si.0),
+            Boundary(before, after, k) => {
+                let s = &graph.rows[id.row][id.col];
+                Position::Boundary(before.clone(), after.clone(), *k)
+                    .run(s)
+                    .map(|si| si.0)
+            }
+        }
+    }
+
     #[cfg(test)]
     fn denote(&self, graph: &InputDataGraph) -> BTreeSet<Position> {
         let mut set: BTreeSet<Position> = BTreeSet::new();
@@ -399,6 +1383,9 @@ impl PositionSet {
             GraphNode(v) => {
                 // find all edges that end at v or start at v
                 for ((vs, vf), tok_occs) in &graph.tokens {
+                    let tok_occs = tok_occs
+                        .iter()
+                        .map(|(id, k)| (graph.interner.token(*id), k));
                     if vs == v {
                         for (tok, occ) in tok_occs {
                             set.insert(Position::Match(tok.clone(), *occ, Direction::Start));
@@ -410,12 +1397,16 @@ impl PositionSet {
                     }
                 }
             }
+            Boundary(before, after, k) => {
+                set.insert(Position::Boundary(before.clone(), after.clone(), *k));
+            }
         }
         set
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::needless_borrow, clippy::useless_vec)]
 mod tests {
     use super::super::token::Token;
     use super::*;
@@ -432,12 +1423,13 @@ mod tests {
             vec!["Wellington, New Zealand"],
             vec!["New Delhi, India"],
         ];
-        let graph = InputDataGraph::new(&strs);
+        let graph = InputDataGraph::new(&strs, &[]);
         // find the substring expression set that generates "India" from the 1st string
         let sub = SubstringExpressionSet::generate_substring_set(
             Id::new(0, 0),
             StringIndex(9),
             StringIndex(14),
+            &InputDataGraph::boundaries(strs[0][0], &[]),
             &graph,
         );
         let sub_denote = sub.denote(&graph);
@@ -458,6 +1450,11 @@ mod tests {
             Position::Match(Token::Alphabets, Occurrence(-1), Direction::Start),
             Position::Match(Token::End, Occurrence(1), Direction::Start)
         )));
+        assert!(sub_denote.contains(&SubstringExpression::Substring(
+            ColumnIndex(0),
+            Position::Boundary(Token::Whitespace, Token::ProperCase, Occurrence(-1)),
+            Position::Match(Token::End, Occurrence(1), Direction::Start)
+        )));
         // even though this next pattern would occur if the graph were built from only the first
         // string, this pattern doesn't match in the other strings, so it does not appear
         assert!(!sub_denote.contains(&SubstringExpression::Substring(
@@ -490,12 +1487,13 @@ mod tests {
     fn generate_substring_set_single() {
         // similar to the negated case from above, with a different graph, should appear
         let strs = vec![vec!["Shrewsbury, MA"], vec!["Shrewsbury, United Kingdom"]];
-        let graph = InputDataGraph::new(&strs);
+        let graph = InputDataGraph::new(&strs, &[]);
         // find the substring expression set that generates "MA" from the 1st string
         let sub = SubstringExpressionSet::generate_substring_set(
             Id::new(0, 0),
             StringIndex(13),
             StringIndex(15),
+            &InputDataGraph::boundaries(strs[0][0], &[]),
             &graph,
         );
         let sub_denote = sub.denote(&graph);
@@ -534,8 +1532,8 @@ mod tests {
             vec!["Wellington, New Zealand"],
             vec!["New Delhi, India"],
         ];
-        let graph = InputDataGraph::new(&strs);
-        let dag = Dag::new(&strs[0], "India", &graph, 0);
+        let graph = InputDataGraph::new(&strs, &[]);
+        let dag = Dag::new(&strs[0], "India", &graph, 0, &[]);
         // some spot checks
         assert!(all_for(&dag, &graph, 0, 3)
             .contains(&SubstringExpression::ConstantString(String::from("Ind"))));
@@ -565,12 +1563,12 @@ mod tests {
             vec!["Wellington, New Zealand"],
             vec!["New Delhi, India"],
         ];
-        let graph = InputDataGraph::new(&strs);
+        let graph = InputDataGraph::new(&strs, &[]);
         let examples = vec![
             (strs[0].clone(), "India"),
             (strs[1].clone(), "United States of America"),
         ];
-        let dag = Dag::learn(&examples, &graph);
+        let dag = Dag::learn(&examples, &graph, &[]);
         // check all expressions that extract output in one go
         let exprs = all_for(&dag, &graph, dag.start, dag.finish);
         for e in &exprs {
@@ -594,7 +1592,7 @@ mod tests {
             Position::Match(Token::End, Occurrence(1), Direction::Start),
         )));
         // check final program
-        let best = dag.top_ranked_expression(&graph).unwrap();
+        let best = dag.top_ranked_expression(&graph, Ranking::Paper).unwrap();
         let expected = vec![
             "United States",
             "United States of America",
@@ -614,13 +1612,13 @@ mod tests {
             vec!["510.220.5586"],
             vec!["(471)-378-3829"],
         ];
-        let graph = InputDataGraph::new(&strs);
+        let graph = InputDataGraph::new(&strs, &[]);
         let examples = vec![
             (strs[0].clone(), "323-708-7700"),
             (strs[1].clone(), "425-706-7709"),
         ];
-        let dag = Dag::learn(&examples, &graph);
-        let best = dag.top_ranked_expression(&graph).unwrap();
+        let dag = Dag::learn(&examples, &graph, &[]);
+        let best = dag.top_ranked_expression(&graph, Ranking::Paper).unwrap();
         let expected = vec!["510-220-5586", "471-378-3829"];
         for (i, s) in strs[2..].iter().enumerate() {
             assert_eq!(best.run(s).unwrap(), expected[i]);
@@ -636,10 +1634,10 @@ mod tests {
             vec!["Danelle D. Saunders"],
             vec!["Emilio William Conception"],
         ];
-        let graph = InputDataGraph::new(&strs);
+        let graph = InputDataGraph::new(&strs, &[]);
         let examples = vec![(strs[0].clone(), "B.S."), (strs[1].clone(), "D.C.")];
-        let dag = Dag::learn(&examples, &graph);
-        let best = dag.top_ranked_expression(&graph).unwrap();
+        let dag = Dag::learn(&examples, &graph, &[]);
+        let best = dag.top_ranked_expression(&graph, Ranking::Paper).unwrap();
         let expected = vec!["W.L.", "D.S.", "E.C."];
         for (i, s) in strs[2..].iter().enumerate() {
             assert_eq!(best.run(s).unwrap(), expected[i]);
@@ -653,10 +1651,10 @@ mod tests {
             vec!["GOPR0411.MP4.mp4"],
             vec!["GOPR0329.MP4.mp4"],
         ];
-        let graph = InputDataGraph::new(&strs);
+        let graph = InputDataGraph::new(&strs, &[]);
         let examples = vec![(strs[0].clone(), "GOPR0365.mp4")];
-        let dag = Dag::learn(&examples, &graph);
-        let best = dag.top_ranked_expression(&graph).unwrap();
+        let dag = Dag::learn(&examples, &graph, &[]);
+        let best = dag.top_ranked_expression(&graph, Ranking::Paper).unwrap();
         let expected = vec!["GOPR0411.mp4", "GOPR0329.mp4"];
         for (i, s) in strs[1..].iter().enumerate() {
             assert_eq!(best.run(s).unwrap(), expected[i]);
@@ -672,10 +1670,10 @@ mod tests {
             vec!["DSC0324.jpg"],
             vec!["RD392.HEIC"],
         ];
-        let graph = InputDataGraph::new(&strs);
+        let graph = InputDataGraph::new(&strs, &[]);
         let examples = vec![(strs[0].clone(), "IMG_3246")];
-        let dag = Dag::learn(&examples, &graph);
-        let best = dag.top_ranked_expression(&graph).unwrap();
+        let dag = Dag::learn(&examples, &graph, &[]);
+        let best = dag.top_ranked_expression(&graph, Ranking::Paper).unwrap();
         let expected = vec!["GOPR0411", "DSC_0324", "DSC0324", "RD392"];
         for (i, s) in strs[1..].iter().enumerate() {
             assert_eq!(best.run(s).unwrap(), expected[i]);
@@ -691,16 +1689,105 @@ mod tests {
             vec!["4", "DSC0324.jpg"],
             vec!["5", "RD392.HEIC"],
         ];
-        let graph = InputDataGraph::new(&strs);
+        let graph = InputDataGraph::new(&strs, &[]);
         let examples = vec![
             (strs[0].clone(), "1_IMG_3246"),
             (strs[1].clone(), "2_GOPR0411"),
         ];
-        let dag = Dag::learn(&examples, &graph);
-        let best = dag.top_ranked_expression(&graph).unwrap();
+        let dag = Dag::learn(&examples, &graph, &[]);
+        let best = dag.top_ranked_expression(&graph, Ranking::Paper).unwrap();
         let expected = vec!["3_DSC_0324", "4_DSC0324", "5_RD392"];
         for (i, s) in strs[2..].iter().enumerate() {
             assert_eq!(best.run(s).unwrap(), expected[i]);
         }
     }
+
+    #[test]
+    fn learn_row_by_row() {
+        // with many rows, intersecting the graphs of the rows and the dags of the examples as a
+        // balanced tree, as the rayon feature does, learns the same programs as intersecting them
+        // one at a time
+        let names = [
+            "Alyssa P. Hacker",
+            "Ben Bitdiddle",
+            "Cy D. Fect",
+            "Eva Lu Ator",
+        ];
+        let data: Vec<String> = (0..500)
+            .map(|i| {
+                let name = names[i % names.len()];
+                format!("{}, {}-{:03} ({})", name, 1900 + i * 7 % 120, i, i % 9)
+            })
+            .collect();
+        let strs: Vec<Vec<&str>> = data.iter().map(|s| vec![s.as_str()]).collect();
+        let outputs = ["1900", "1907", "1914", "1921", "1928"];
+        let examples: Vec<(Vec<&str>, &str)> = strs.iter().cloned().zip(outputs).collect();
+
+        let graph = InputDataGraph::new(&strs, &[]);
+        let dag = Dag::learn(&examples, &graph, &[]);
+        let row_by_row = InputDataGraph::new_row_by_row(&strs, &[]);
+        let folded = examples
+            .iter()
+            .enumerate()
+            .map(|(row, (input, output))| Dag::new(input, output, &row_by_row, row, &[]))
+            .reduce(|acc, x| acc.intersection(&x))
+            .unwrap();
+        assert_eq!(graph.nodes().len(), row_by_row.nodes().len());
+        assert_eq!(graph.edges().len(), row_by_row.edges().len());
+        for ranking in [Ranking::Paper, Ranking::Validated] {
+            let top = dag.top_k_expressions(&graph, 10, ranking);
+            assert!(!top.is_empty());
+            assert_eq!(top, folded.top_k_expressions(&row_by_row, 10, ranking));
+        }
+    }
+
+    #[test]
+    fn learn_loop() {
+        let strs = vec![
+            vec!["alpha, beta"],
+            vec!["one, two, three"],
+            vec!["red, green, blue"],
+            vec!["x"],
+        ];
+        let graph = InputDataGraph::new(&strs, &[]);
+        let examples = vec![
+            (strs[0].clone(), "ALPHA;BETA"),
+            (strs[1].clone(), "ONE;TWO;THREE"),
+        ];
+        let dag = Dag::learn(&examples, &graph, &[]);
+        let best = dag.top_ranked_expression(&graph, Ranking::Paper).unwrap();
+        assert_eq!(best.parts().len(), 1);
+        let (ci, _, body, sep) = best.parts()[0].loop_parts().unwrap();
+        assert_eq!(ci, ColumnIndex(0));
+        assert_eq!(body.conversion().unwrap().0, Case::Upper);
+        assert_eq!(sep, ";");
+        assert_eq!(best.run(&strs[2]).unwrap(), "RED;GREEN;BLUE");
+        assert_eq!(best.run(&strs[3]).unwrap(), "X");
+    }
+
+    #[test]
+    fn loop_separator() {
+        // a single match leaves the separator open, until an example with more matches fixes it
+        let strs = vec![vec!["ab"], vec!["cd ef"]];
+        let graph = InputDataGraph::new(&strs, &[]);
+        let dag = Dag::new(&strs[0], "ab", &graph, 0, &[]);
+        let loops: Vec<_> = dag.substrings[&(0, 2)]
+            .iter()
+            .filter(|e| matches!(e, LoopSet(_, Token::Lowercase, _, _, _)))
+            .collect();
+        assert_eq!(loops.len(), 1);
+        assert!(matches!(loops[0], LoopSet(_, _, None, _, 2)));
+        let dag = dag.intersection(&Dag::new(&strs[1], "cd-ef", &graph, 1, &[]));
+        assert!(dag.substrings.values().flatten().any(|e| matches!(
+            e,
+            LoopSet(_, Token::Lowercase, Some(sep), _, 7) if sep == "-"
+        )));
+        // separators don't contain letters or digits
+        let dag = Dag::new(&strs[1], "cdxef", &graph, 1, &[]);
+        assert!(!dag
+            .substrings
+            .values()
+            .flatten()
+            .any(|e| matches!(e, LoopSet(_, _, Some(_), _, _))));
+    }
 }
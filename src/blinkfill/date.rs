@@ -0,0 +1,682 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const WEEKDAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+// the most components that a date in the input can have, such as "Sat, Jul 3, 2021 10:30:00"
+const MAX_COMPONENTS: usize = 7;
+
+// the most ways of reading the components of a string as fields that are tried, because a run of
+// small numbers like "01-02-03 04:05:06" can be read in hundreds of thousands of ways
+const MAX_CHOICES: usize = 256;
+
+/// A way of writing a date and time, such as "03/07/2021", "2021-07-03", or "Jul 3, 2021".
+///
+/// Formats are written as patterns with `strftime`-style directives, so the examples above are
+/// written as `%d/%m/%Y`, `%Y-%m-%d`, and `%b %-d, %Y`. The supported directives are:
+///
+/// | Directive | Meaning | Example |
+/// |-----------|---------|---------|
+/// | `%Y` | year with four digits | 2021 |
+/// | `%y` | year with two digits, from 1969 to 2068 | 21 |
+/// | `%m`, `%-m` | month with two digits, or without padding | 07, 7 |
+/// | `%b`, `%B` | abbreviated or full month name | Jul, July |
+/// | `%d`, `%-d` | day of the month with two digits, or without padding | 03, 3 |
+/// | `%a`, `%A` | abbreviated or full weekday name | Sat, Saturday |
+/// | `%H`, `%-H` | hour from 0 to 23 with two digits, or without padding | 09, 9 |
+/// | `%M` | minute with two digits | 05 |
+/// | `%S` | second with two digits | 00 |
+/// | `%%` | a literal `%` | % |
+///
+/// When parsing, padding is optional, and names are matched regardless of case. A weekday is
+/// checked against the date, if the date has a year.
+#[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord)]
+pub struct DateFormat(Vec<Item>);
+
+#[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord)]
+enum Item {
+    Field(Field),
+    Literal(String),
+}
+
+// the order of the fields decides ties between formats that are equally good, so day-first
+// formats come before month-first formats
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
+enum Field {
+    Year,
+    ShortYear,
+    Day,
+    DayUnpadded,
+    Month,
+    MonthUnpadded,
+    MonthAbbr,
+    MonthName,
+    WeekdayAbbr,
+    WeekdayName,
+    Hour,
+    HourUnpadded,
+    Minute,
+    Second,
+}
+
+use Field::*;
+
+const DIRECTIVES: [(&str, Field); 14] = [
+    ("Y", Year),
+    ("y", ShortYear),
+    ("d", Day),
+    ("-d", DayUnpadded),
+    ("m", Month),
+    ("-m", MonthUnpadded),
+    ("b", MonthAbbr),
+    ("B", MonthName),
+    ("a", WeekdayAbbr),
+    ("A", WeekdayName),
+    ("H", Hour),
+    ("-H", HourUnpadded),
+    ("M", Minute),
+    ("S", Second),
+];
+
+// the part of a date that a field sets, so that a format sets each part at most once
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Part {
+    Year,
+    Month,
+    Day,
+    Weekday,
+    Hour,
+    Minute,
+    Second,
+}
+
+impl Field {
+    fn directive(self) -> &'static str {
+        DIRECTIVES.iter().find(|(_, f)| *f == self).unwrap().0
+    }
+
+    fn part(self) -> Part {
+        match self {
+            Year | ShortYear => Part::Year,
+            Month | MonthUnpadded | MonthAbbr | MonthName => Part::Month,
+            Day | DayUnpadded => Part::Day,
+            WeekdayAbbr | WeekdayName => Part::Weekday,
+            Hour | HourUnpadded => Part::Hour,
+            Minute => Part::Minute,
+            Second => Part::Second,
+        }
+    }
+
+    // parses the field at the start of s, returning its value and length
+    fn parse(self, s: &str) -> Option<(u32, usize)> {
+        let (min, max) = match self {
+            Year => (4, 4),
+            ShortYear => (2, 2),
+            MonthAbbr => return parse_name(s, &MONTHS, true).map(|i| (i + 1, 3)),
+            MonthName => {
+                return parse_name(s, &MONTHS, false).map(|i| (i + 1, MONTHS[i as usize].len()))
+            }
+            WeekdayAbbr => return parse_name(s, &WEEKDAYS, true).map(|i| (i, 3)),
+            WeekdayName => {
+                return parse_name(s, &WEEKDAYS, false).map(|i| (i, WEEKDAYS[i as usize].len()))
+            }
+            _ => (1, 2),
+        };
+        let len = s.bytes().take(max).take_while(u8::is_ascii_digit).count();
+        if len < min {
+            return None;
+        }
+        let value = s[..len].parse().ok()?;
+        let value = match self {
+            ShortYear if value < 69 => 2000 + value,
+            ShortYear => 1900 + value,
+            _ => value,
+        };
+        Some((value, len))
+    }
+
+    fn format(self, date: &Date) -> Option<String> {
+        let s = match self {
+            Year => format!("{:04}", date.year.filter(|y| *y < 10000)?),
+            ShortYear => format!("{:02}", date.year? % 100),
+            Month => format!("{:02}", date.month?),
+            MonthUnpadded => date.month?.to_string(),
+            MonthAbbr => String::from(&MONTHS[date.month? as usize - 1][..3]),
+            MonthName => String::from(MONTHS[date.month? as usize - 1]),
+            Day => format!("{:02}", date.day?),
+            DayUnpadded => date.day?.to_string(),
+            WeekdayAbbr => String::from(&WEEKDAYS[date.weekday()? as usize][..3]),
+            WeekdayName => String::from(WEEKDAYS[date.weekday()? as usize]),
+            Hour => format!("{:02}", date.hour?),
+            HourUnpadded => date.hour?.to_string(),
+            Minute => format!("{:02}", date.minute?),
+            Second => format!("{:02}", date.second?),
+        };
+        Some(s)
+    }
+}
+
+// finds the name at the start of s, ignoring case, and returns its index
+fn parse_name(s: &str, names: &[&str], abbreviated: bool) -> Option<u32> {
+    names
+        .iter()
+        .position(|name| {
+            let name = if abbreviated { &name[..3] } else { name };
+            s.get(..name.len())
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(name))
+        })
+        .map(|i| i as u32)
+}
+
+/// A date and time, with the parts that a format didn't contain left out.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, PartialOrd, Ord)]
+pub(crate) struct Date {
+    year: Option<u32>,
+    month: Option<u32>,
+    day: Option<u32>,
+    // zero for Monday; only set if it was parsed
+    weekday: Option<u32>,
+    hour: Option<u32>,
+    minute: Option<u32>,
+    second: Option<u32>,
+}
+
+impl Date {
+    fn set(&mut self, part: Part, value: u32) -> Option<()> {
+        let slot = match part {
+            Part::Year => &mut self.year,
+            Part::Month => &mut self.month,
+            Part::Day => &mut self.day,
+            Part::Weekday => &mut self.weekday,
+            Part::Hour => &mut self.hour,
+            Part::Minute => &mut self.minute,
+            Part::Second => &mut self.second,
+        };
+        match slot {
+            Some(v) if *v != value => None,
+            _ => {
+                *slot = Some(value);
+                Some(())
+            }
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        let max_day = match (self.year, self.month) {
+            (_, Some(m)) if !(1..=12).contains(&m) => return false,
+            (Some(y), Some(m)) => days_in_month(y, m),
+            // without a year, February 29 might exist
+            (None, Some(m)) => days_in_month(2000, m),
+            (_, None) => 31,
+        };
+        let weekday_ok = match (self.weekday, self.computed_weekday()) {
+            (Some(w1), Some(w2)) => w1 == w2,
+            _ => true,
+        };
+        self.day.map_or(true, |d| 1 <= d && d <= max_day)
+            && self.hour.map_or(true, |h| h < 24)
+            && self.minute.map_or(true, |m| m < 60)
+            && self.second.map_or(true, |s| s < 60)
+            && weekday_ok
+    }
+
+    fn weekday(&self) -> Option<u32> {
+        self.computed_weekday().or(self.weekday)
+    }
+
+    // the day of the week, from the number of days since 1970-01-01, which was a Thursday; this
+    // is the days_from_civil algorithm from http://howardhinnant.github.io/date_algorithms.html
+    fn computed_weekday(&self) -> Option<u32> {
+        let (y, m, d) = (self.year? as i64, self.month? as i64, self.day? as i64);
+        let y = if m <= 2 { y - 1 } else { y };
+        let era = y.div_euclid(400);
+        let yoe = y - era * 400;
+        let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146097 + doe - 719468;
+        Some((days + 3).rem_euclid(7) as u32)
+    }
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl DateFormat {
+    // parses a pattern, returning an error message if it has an unknown directive
+    pub(crate) fn from_pattern(pattern: &str) -> Result<Self, String> {
+        let mut items = vec![];
+        let mut rest = pattern;
+        while let Some(i) = rest.find('%') {
+            push_literal(&mut items, &rest[..i]);
+            rest = &rest[i + 1..];
+            if let Some(r) = rest.strip_prefix('%') {
+                push_literal(&mut items, "%");
+                rest = r;
+                continue;
+            }
+            // the longest directive that matches wins, so that %-d isn't read as %- and d
+            let (directive, field) = DIRECTIVES
+                .iter()
+                .filter(|(d, _)| rest.starts_with(d))
+                .max_by_key(|(d, _)| d.len())
+                .ok_or_else(|| match rest.chars().next() {
+                    Some(c) => format!("unknown date directive `%{}`", c),
+                    None => String::from("incomplete date directive `%`"),
+                })?;
+            items.push(Item::Field(*field));
+            rest = &rest[directive.len()..];
+        }
+        push_literal(&mut items, rest);
+        Ok(DateFormat(items))
+    }
+
+    /// Parses a date in this format, or returns `None` if the string isn't a valid date in this
+    /// format.
+    pub(crate) fn parse(&self, s: &str) -> Option<Date> {
+        let mut date = Date::default();
+        let mut rest = s;
+        for item in &self.0 {
+            match item {
+                Item::Literal(lit) => rest = rest.strip_prefix(lit.as_str())?,
+                Item::Field(field) => {
+                    let (value, len) = field.parse(rest)?;
+                    date.set(field.part(), value)?;
+                    rest = &rest[len..];
+                }
+            }
+        }
+        if rest.is_empty() && date.is_valid() {
+            Some(date)
+        } else {
+            None
+        }
+    }
+
+    /// Writes a date in this format, or returns `None` if the date is missing a part that this
+    /// format needs.
+    pub(crate) fn format(&self, date: &Date) -> Option<String> {
+        let mut result = String::new();
+        for item in &self.0 {
+            match item {
+                Item::Literal(lit) => result.push_str(lit),
+                Item::Field(field) => result.push_str(&field.format(date)?),
+            }
+        }
+        Some(result)
+    }
+
+    // finds all of the formats that write the date as s; s has to start and end with a field,
+    // and everything between the fields is literal punctuation or whitespace
+    pub(crate) fn formats_for(date: &Date, s: &str) -> BTreeSet<Self> {
+        let components = components(s);
+        let mut formats = BTreeSet::new();
+        match (components.first(), components.last()) {
+            (Some((start, _)), Some((_, end))) if *start == 0 && *end == s.len() => {}
+            _ => return formats,
+        }
+        let options: Vec<Vec<Field>> = components
+            .iter()
+            .map(|(l, r)| {
+                DIRECTIVES
+                    .iter()
+                    .map(|(_, f)| *f)
+                    .filter(|f| f.format(date).as_deref() == Some(&s[*l..*r]))
+                    .collect()
+            })
+            .collect();
+        for fields in product(&options, |_, _| true) {
+            formats.insert(build(s, &components, &fields));
+        }
+        formats
+    }
+}
+
+// adds a literal to a list of items, merging it with a literal before it
+fn push_literal(items: &mut Vec<Item>, s: &str) {
+    if s.is_empty() {
+        return;
+    }
+    match items.last_mut() {
+        Some(Item::Literal(lit)) => lit.push_str(s),
+        _ => items.push(Item::Literal(String::from(s))),
+    }
+}
+
+// builds the format for a string whose components are the given fields, with the text in between
+// the components as literals
+fn build(s: &str, components: &[(usize, usize)], fields: &[Field]) -> DateFormat {
+    let mut items = vec![];
+    let mut prev = components[0].0;
+    for ((l, r), field) in components.iter().zip(fields) {
+        push_literal(&mut items, &s[prev..*l]);
+        items.push(Item::Field(*field));
+        prev = *r;
+    }
+    DateFormat(items)
+}
+
+// the ways of choosing one option for each component, where each option has to be allowed by keep
+// given the options chosen before it; at most MAX_CHOICES of them are kept, preferring earlier
+// options
+fn product(options: &[Vec<Field>], keep: impl Fn(&[Field], Field) -> bool) -> Vec<Vec<Field>> {
+    let keep = &keep;
+    options.iter().fold(vec![vec![]], |acc, opts| {
+        acc.iter()
+            .flat_map(|prefix| {
+                opts.iter()
+                    .filter(move |f| keep(prefix, **f))
+                    .map(move |f| {
+                        let mut fields = prefix.clone();
+                        fields.push(*f);
+                        fields
+                    })
+            })
+            .take(MAX_CHOICES)
+            .collect()
+    })
+}
+
+impl fmt::Display for DateFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for item in &self.0 {
+            match item {
+                Item::Field(field) => write!(f, "%{}", field.directive())?,
+                Item::Literal(lit) => f.write_str(&lit.replace('%', "%%"))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// splits a string into its components, which are the maximal runs of ASCII digits and the
+// maximal runs of letters, as byte ranges
+fn components(s: &str) -> Vec<(usize, usize)> {
+    let mut components = vec![];
+    let mut current: Option<(usize, bool)> = None;
+    for (i, c) in s.char_indices() {
+        let kind = if c.is_ascii_digit() {
+            Some(true)
+        } else if c.is_alphabetic() {
+            Some(false)
+        } else {
+            None
+        };
+        match (current, kind) {
+            (Some((_, digits)), Some(k)) if digits == k => {}
+            _ => {
+                if let Some((start, _)) = current {
+                    components.push((start, i));
+                }
+                current = kind.map(|k| (i, k));
+            }
+        }
+    }
+    if let Some((start, _)) = current {
+        components.push((start, s.len()));
+    }
+    components
+}
+
+// the fields that a component of a date in the input could be; numbers are parsed with the padded
+// fields, which accept unpadded numbers too
+fn input_fields(c: &str) -> Vec<Field> {
+    if c.bytes().all(|b| b.is_ascii_digit()) {
+        return match c.len() {
+            4 => vec![Year],
+            2 => vec![ShortYear, Day, Month, Hour, Minute, Second],
+            1 => vec![Day, Month, Hour, Minute, Second],
+            _ => vec![],
+        };
+    }
+    let mut fields = vec![];
+    let mut add = |field: Field| {
+        if field.parse(c).is_some_and(|(_, len)| len == c.len()) {
+            fields.push(field);
+        }
+    };
+    add(MonthAbbr);
+    add(MonthName);
+    add(WeekdayAbbr);
+    add(WeekdayName);
+    fields
+}
+
+// checks that the fields make up a date, with the parts in a conventional order; the separators
+// are the text in between the fields, and colons separate the parts of the time and nothing else
+fn is_date(fields: &[Field], separators: &[&str]) -> bool {
+    let parts: Vec<Part> = fields.iter().map(|f| f.part()).collect();
+    let position = |part| parts.iter().position(|p| *p == part);
+    for (i, p) in parts.iter().enumerate() {
+        if parts[i + 1..].contains(p) {
+            return false;
+        }
+    }
+    let date_order: Vec<Part> = parts
+        .iter()
+        .cloned()
+        .filter(|p| matches!(p, Part::Year | Part::Month | Part::Day))
+        .collect();
+    let conventional = [
+        &[Part::Year, Part::Month, Part::Day][..],
+        &[Part::Month, Part::Day, Part::Year],
+        &[Part::Day, Part::Month, Part::Year],
+        &[Part::Month, Part::Day],
+        &[Part::Day, Part::Month],
+    ];
+    let (hour, minute, second) = (
+        position(Part::Hour),
+        position(Part::Minute),
+        position(Part::Second),
+    );
+    let time_ok = match (hour, minute, second) {
+        (None, None, None) => true,
+        (Some(h), Some(m), None) => m == h + 1,
+        (Some(h), Some(m), Some(s)) => m == h + 1 && s == m + 1,
+        _ => false,
+    };
+    let is_time = |part| matches!(part, Part::Hour | Part::Minute | Part::Second);
+    let colons_ok = separators.iter().enumerate().all(|(k, sep)| {
+        let between_time = is_time(parts[k]) && is_time(parts[k + 1]);
+        between_time == (*sep == ":")
+    });
+    conventional.contains(&date_order.as_slice()) && time_ok && colons_ok
+}
+
+// a substring of the input that looks like a date, as a byte range, with the formats that it could
+// be in and the date that it is in each format
+pub(crate) type DateCandidate = (usize, usize, Vec<(DateFormat, Date)>);
+
+// finds the substrings of s that look like dates; a date is a sequence of numbers and month or
+// weekday names, separated by a few punctuation or whitespace characters, or by a T between
+// numbers as in ISO 8601
+pub(crate) fn find_dates(s: &str) -> Vec<DateCandidate> {
+    // drop the T in things like 2021-07-03T10:30, so it's part of the separator
+    let all = components(s);
+    let mut components: Vec<(usize, usize)> = vec![];
+    for (k, (l, r)) in all.iter().enumerate() {
+        let digit_at = |i: usize| s.as_bytes().get(i).is_some_and(u8::is_ascii_digit);
+        if &s[*l..*r] == "T" && k > 0 && all[k - 1].1 == *l && digit_at(*r) {
+            continue;
+        }
+        components.push((*l, *r));
+    }
+
+    // split the components into chains that are separated by valid separators
+    let is_separator = |sep: &str| {
+        sep == "T" || (1..=3).contains(&sep.len()) && sep.chars().all(|c| " ,/.-:".contains(c))
+    };
+    let mut chains: Vec<Vec<(usize, usize)>> = vec![];
+    for (k, c) in components.iter().enumerate() {
+        match chains.last_mut() {
+            Some(chain) if k > 0 && is_separator(&s[components[k - 1].1..c.0]) => chain.push(*c),
+            _ => chains.push(vec![*c]),
+        }
+    }
+
+    let mut dates = vec![];
+    for chain in chains {
+        for a in 0..chain.len() {
+            for b in a + 2..(a + MAX_COMPONENTS).min(chain.len()) + 1 {
+                let span = &chain[a..b];
+                let (l, r) = (span[0].0, span[b - a - 1].1);
+                let options: Vec<Vec<Field>> =
+                    span.iter().map(|(l, r)| input_fields(&s[*l..*r])).collect();
+                let separators: Vec<&str> = span.windows(2).map(|w| &s[w[0].1..w[1].0]).collect();
+                let mut formats = vec![];
+                // leave out the choices that can't be dates because they repeat a part or have
+                // colons anywhere but between the parts of the time, before there are too many
+                let is_time =
+                    |f: Field| matches!(f.part(), Part::Hour | Part::Minute | Part::Second);
+                let keep = |prefix: &[Field], f: Field| {
+                    let repeated = prefix.iter().any(|p| p.part() == f.part());
+                    let colons_ok = prefix.last().map_or(true, |prev| {
+                        (is_time(*prev) && is_time(f)) == (separators[prefix.len() - 1] == ":")
+                    });
+                    !repeated && colons_ok
+                };
+                for fields in product(&options, keep) {
+                    if !is_date(&fields, &separators) {
+                        continue;
+                    }
+                    let format = build(s, span, &fields);
+                    if let Some(date) = format.parse(&s[l..r]) {
+                        formats.push((format, date));
+                    }
+                }
+                if !formats.is_empty() {
+                    dates.push((l, r, formats));
+                }
+            }
+        }
+    }
+    dates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(pattern: &str) -> DateFormat {
+        DateFormat::from_pattern(pattern).unwrap()
+    }
+
+    fn reformat(input: &str, output: &str, s: &str) -> Option<String> {
+        format(output).format(&format(input).parse(s)?)
+    }
+
+    #[test]
+    fn patterns() {
+        let f = format("%-d %b %Y, %H:%M (100%%)");
+        assert_eq!(f.to_string(), "%-d %b %Y, %H:%M (100%%)");
+        assert_eq!(
+            DateFormat::from_pattern("%d/%q"),
+            Err(String::from("unknown date directive `%q`"))
+        );
+        assert_eq!(
+            DateFormat::from_pattern("%d%"),
+            Err(String::from("incomplete date directive `%`"))
+        );
+    }
+
+    #[test]
+    fn parse_and_format() {
+        assert_eq!(
+            reformat("%d/%m/%Y", "%Y-%m-%d", "3/7/2021").unwrap(),
+            "2021-07-03"
+        );
+        assert_eq!(
+            reformat("%b %d, %Y", "%A, %B %-d", "jul 03, 2021").unwrap(),
+            "Saturday, July 3"
+        );
+        assert_eq!(reformat("%d.%m.%y", "%Y", "1.1.69").unwrap(), "1969");
+        assert_eq!(reformat("%d.%m.%y", "%Y", "1.1.68").unwrap(), "2068");
+        assert_eq!(
+            reformat("%Y-%m-%dT%H:%M", "%-H:%M on %d/%m", "2021-07-03T09:05").unwrap(),
+            "9:05 on 03/07"
+        );
+        // invalid dates
+        assert_eq!(reformat("%m/%d/%Y", "%Y", "13/07/2021"), None);
+        assert_eq!(reformat("%d/%m/%Y", "%Y", "29/02/2021"), None);
+        assert_eq!(reformat("%d/%m/%Y", "%Y", "29/02/2020").unwrap(), "2020");
+        assert_eq!(reformat("%a %d/%m/%Y", "%Y", "Fri 03/07/2021"), None);
+        assert_eq!(reformat("%d/%m/%Y", "%Y", "03/07/2021 "), None);
+        // missing parts
+        assert_eq!(reformat("%d/%m", "%Y", "03/07"), None);
+    }
+
+    #[test]
+    fn formats_for() {
+        let date = format("%Y-%m-%d").parse("2021-07-07").unwrap();
+        let formats: Vec<String> = DateFormat::formats_for(&date, "07/07/21")
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        assert_eq!(
+            formats,
+            vec!["%d/%d/%y", "%d/%m/%y", "%m/%d/%y", "%m/%m/%y"]
+        );
+        let formats = DateFormat::formats_for(&date, "July 7, 2021");
+        assert_eq!(formats.len(), 2);
+        assert!(formats.contains(&format("%B %-d, %Y")));
+        assert!(DateFormat::formats_for(&date, "July 7,").is_empty());
+        assert!(DateFormat::formats_for(&date, "Jul 8").is_empty());
+    }
+
+    #[test]
+    fn find_dates() {
+        let dates = super::find_dates("due 03/07/2021, or Jul 3");
+        let spans: Vec<(usize, usize)> = dates.iter().map(|(l, r, _)| (*l, *r)).collect();
+        assert_eq!(spans, vec![(4, 9), (4, 14), (19, 24)]);
+        let formats: Vec<String> = dates[1].2.iter().map(|(f, _)| f.to_string()).collect();
+        assert_eq!(formats, vec!["%d/%m/%Y", "%m/%d/%Y"]);
+        let dates = super::find_dates("2021-07-03T10:30:00");
+        let formats: Vec<String> = dates
+            .iter()
+            .flat_map(|(_, _, formats)| formats.iter().map(|(f, _)| f.to_string()))
+            .collect();
+        assert!(formats.contains(&String::from("%Y-%m-%dT%H:%M:%S")));
+        assert!(super::find_dates("617-253-1234 at 10:30").is_empty());
+        let dates = super::find_dates("03/07/21 10:30:00");
+        assert!(dates
+            .iter()
+            .flat_map(|(_, _, formats)| formats)
+            .any(|(f, _)| f.to_string() == "%d/%m/%y %H:%M:%S"));
+        // long runs of small numbers have a bounded number of readings, so this finishes quickly
+        let s = (1..60)
+            .map(|i| format!("{:02}", i))
+            .collect::<Vec<_>>()
+            .join("-");
+        assert!(!super::find_dates(&s).is_empty());
+        // and times alone aren't dates
+        assert!(super::find_dates(&s.replace('-', ":")).is_empty());
+    }
+}
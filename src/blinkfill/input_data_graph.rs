@@ -1,6 +1,8 @@
-use super::language::{Occurrence, StringIndex};
-use super::token::{Token, ALL_RE_TOKENS};
+use super::language::{Direction, Occurrence, Position, StringIndex};
+use super::suffix_array::SuffixArray;
+use super::token::Token;
 use crate::graph;
+use crate::par;
 use std::cmp;
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
@@ -20,37 +22,142 @@ impl Id {
 type Node = usize;
 type Edge = (Node, Node);
 
+// an interned token; the graphs that are intersected have to share an interner
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord, Hash)]
+pub struct TokenId(u32);
+
+// the tokens on an edge with their occurrences, in sorted order and without duplicates, so sets
+// can be intersected by merging them
+pub type TokenSet = Vec<(TokenId, Occurrence)>;
+
+#[derive(Debug, Default)]
+pub struct Interner {
+    ids: HashMap<Token, TokenId>,
+    tokens: Vec<Token>,
+}
+
+impl Interner {
+    pub fn intern(&mut self, token: Token) -> TokenId {
+        if let Some(id) = self.ids.get(&token) {
+            return *id;
+        }
+        let id = TokenId(self.tokens.len() as u32);
+        self.tokens.push(token.clone());
+        self.ids.insert(token, id);
+        id
+    }
+
+    pub fn id(&self, token: &Token) -> Option<TokenId> {
+        self.ids.get(token).copied()
+    }
+
+    pub fn token(&self, id: TokenId) -> &Token {
+        &self.tokens[id.0 as usize]
+    }
+}
+
+// how the tokens of a string are interned: the first row of a column adds its tokens to the
+// interner, and the other rows only keep the tokens that the first row has, because no other
+// token can be in the intersection of the rows; this lets the other rows be built in parallel
+enum Interning<'a> {
+    Add(&'a mut Interner),
+    Lookup(&'a Interner),
+}
+
+impl Interning<'_> {
+    fn intern(&mut self, token: Token) -> Option<TokenId> {
+        match self {
+            Interning::Add(interner) => Some(interner.intern(token)),
+            Interning::Lookup(interner) => interner.id(&token),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct InputDataGraph {
     pub labels: BTreeMap<Node, BTreeMap<Id, StringIndex>>,
-    pub tokens: BTreeMap<Edge, BTreeSet<(Token, Occurrence)>>,
+    pub tokens: BTreeMap<Edge, TokenSet>,
+    // the interner that the tokens on the edges are from
+    pub interner: Interner,
+    // the rows that the graph was built from, for evaluating expressions on all of the inputs
+    pub rows: Vec<Vec<String>>,
+    // the custom tokens that the graph was built with, which are indexed like the regex tokens
+    pub custom_tokens: Vec<Token>,
 }
 
 impl InputDataGraph {
     // Requires strs to be non-jagged
-    pub fn new(strs: &[Vec<&str>]) -> Self {
+    pub fn new(strs: &[Vec<&str>], custom_tokens: &[Token]) -> Self {
         let rows = strs.len();
         if rows == 0 {
             return Self::empty();
         }
         let cols = strs[0].len();
-        Self::union((0..cols).map(|col| {
-            (0..rows)
-                .map(|row| Self::from_str(strs[row][col], Id { row, col }))
-                .fold(None, |acc, x| -> Option<Self> {
-                    match acc {
-                        Some(acc) => Some(acc.intersection(&x)),
-                        None => Some(x),
-                    }
+        // the columns are independent
+        let columns = par::map(0..cols, |col| Self::column(strs, col, custom_tokens));
+        let mut graph = Self::union(columns.into_iter());
+        graph.rows = strs
+            .iter()
+            .map(|row| row.iter().map(|s| String::from(*s)).collect())
+            .collect();
+        graph.custom_tokens = custom_tokens.to_vec();
+        graph
+    }
+
+    // the intersection of the graphs of the rows in a column, with its own interner
+    fn column(strs: &[Vec<&str>], col: usize, custom_tokens: &[Token]) -> Self {
+        let mut interner = Interner::default();
+        let first = Self::from_str(
+            strs[0][col],
+            Id { row: 0, col },
+            custom_tokens,
+            &mut Interning::Add(&mut interner),
+        );
+        let interner_ref = &interner;
+        let row = |row: usize| {
+            let id = Id { row, col };
+            let mut interning = Interning::Lookup(interner_ref);
+            Self::from_str(strs[row][col], id, custom_tokens, &mut interning)
+        };
+        let intersect = |g1: Self, g2: Self| g1.intersection(&g2);
+        let mut graph = par::map_fold(first, 1..strs.len(), &row, &intersect);
+        graph.interner = interner;
+        graph
+    }
+
+    // builds the graph like new, but always intersects the rows of each column one at a time from
+    // the first, rather than as a balanced tree with the rayon feature
+    #[cfg(test)]
+    pub fn new_row_by_row(strs: &[Vec<&str>], custom_tokens: &[Token]) -> Self {
+        let columns = (0..strs[0].len()).map(|col| {
+            let mut interner = Interner::default();
+            let mut graph = (0..strs.len())
+                .map(|row| {
+                    let id = Id { row, col };
+                    let mut interning = Interning::Add(&mut interner);
+                    Self::from_str(strs[row][col], id, custom_tokens, &mut interning)
                 })
-                .unwrap()
-        }))
+                .reduce(|acc, x| acc.intersection(&x))
+                .unwrap();
+            graph.interner = interner;
+            graph
+        });
+        let mut graph = Self::union(columns);
+        graph.rows = strs
+            .iter()
+            .map(|row| row.iter().map(|s| String::from(*s)).collect())
+            .collect();
+        graph.custom_tokens = custom_tokens.to_vec();
+        graph
     }
 
     fn empty() -> Self {
         Self {
             labels: BTreeMap::new(),
             tokens: BTreeMap::new(),
+            interner: Interner::default(),
+            rows: Vec::new(),
+            custom_tokens: Vec::new(),
         }
     }
 
@@ -62,29 +169,62 @@ impl InputDataGraph {
         self.tokens.keys()
     }
 
-    fn from_str(s: &str, id: Id) -> Self {
+    // builds the graph of a single string, with its tokens interned in the given interner, which
+    // the graph doesn't keep; tokens that the interner doesn't have are left out
+    fn from_str(s: &str, id: Id, custom_tokens: &[Token], interning: &mut Interning) -> Self {
         let mut labels = BTreeMap::new();
-        let mut tokens = BTreeMap::new();
+        // the nodes are indices, which count characters
+        let chars: Vec<char> = s.chars().collect();
+        let n = chars.len();
+        // most of the edges only have a literal token, so the others are added to these
+        let mut tokens = Self::literal_tokens(&chars, interning);
 
-        for i in 0..s.len() + 3 {
+        for i in 0..n + 3 {
             let mut label = BTreeMap::new();
             label.insert(id, StringIndex(i));
             labels.insert(i, label);
         }
 
-        let mut t_start = BTreeSet::new();
-        t_start.insert((Token::Start, Occurrence(1)));
         // note: we are not inserting start/end with occurrence -1, because this matches exactly
         // once in any string, so it would be redundant to include the other (they would always
         // occur together)
-        tokens.insert((0, 1), t_start);
-        let mut t_end = BTreeSet::new();
-        t_end.insert((Token::End, Occurrence(1)));
-        tokens.insert((s.len() + 1, s.len() + 2), t_end);
+        if let Some(start) = interning.intern(Token::Start) {
+            tokens.insert((0, 1), vec![(start, Occurrence(1))]);
+        }
+        if let Some(end) = interning.intern(Token::End) {
+            tokens.insert((n + 1, n + 2), vec![(end, Occurrence(1))]);
+        }
 
         // this is in terms of adjusted indices
-        for token in ALL_RE_TOKENS {
-            let matches = token.all_matches(s);
+        for (edge, toks) in Self::regex_tokens(s, custom_tokens) {
+            let toks: TokenSet = toks
+                .into_iter()
+                .filter_map(|(tok, k)| Some((interning.intern(tok)?, k)))
+                .collect();
+            if !toks.is_empty() {
+                let set = tokens.entry(edge).or_default();
+                set.extend(toks);
+                set.sort_unstable();
+            }
+        }
+
+        Self {
+            labels,
+            tokens,
+            interner: Interner::default(),
+            rows: Vec::new(),
+            custom_tokens: Vec::new(),
+        }
+    }
+
+    // the edges of the graph for a string that are labeled with the matches of regex tokens,
+    // including the custom ones
+    fn regex_tokens(
+        s: &str,
+        custom_tokens: &[Token],
+    ) -> BTreeMap<Edge, BTreeSet<(Token, Occurrence)>> {
+        let mut tokens = BTreeMap::new();
+        for (token, matches) in Token::all_re_matches(s, custom_tokens) {
             let n = matches.len() as isize;
             for (i, span) in matches.iter().enumerate() {
                 let set = tokens
@@ -95,32 +235,120 @@ impl InputDataGraph {
                 set.insert((token.clone(), Occurrence(i - n)));
             }
         }
+        tokens
+    }
+
+    // the edges of the graph for a string that are labeled with literal tokens; every substring is
+    // a literal token, whose matches are its non-overlapping occurrences from left to right. The
+    // occurrences of each substring are grouped with a suffix array, so this takes time in
+    // proportion to the number of edges, rather than searching for every substring separately.
+    fn literal_tokens(chars: &[char], interning: &mut Interning) -> BTreeMap<Edge, TokenSet> {
+        let suffixes = SuffixArray::new(chars);
+        let mut tokens = BTreeMap::new();
+        for len in 1..chars.len() + 1 {
+            for starts in suffixes.equal_substrings(len) {
+                let mut matches: Vec<usize> = Vec::with_capacity(starts.len());
+                for start in starts {
+                    if matches.last().map_or(true, |prev| start >= prev + len) {
+                        matches.push(start);
+                    }
+                }
+                let token = Token::Literal(chars[matches[0]..matches[0] + len].iter().collect());
+                let token = match interning.intern(token) {
+                    Some(token) => token,
+                    None => continue,
+                };
+                let n = matches.len() as isize;
+                for (i, start) in matches.into_iter().enumerate() {
+                    let i = i as isize;
+                    // the occurrence from the end is negative, so it comes first
+                    let toks = vec![(token, Occurrence(i - n)), (token, Occurrence(i + 1))];
+                    tokens.insert((start + 1, start + len + 1), toks);
+                }
+            }
+        }
+        tokens
+    }
 
-        for i in 1..s.len() + 1 {
-            for j in i + 1..s.len() + 2 {
-                // these indices are 0-based so we can actually index into the slice; also,
-                // right_index is exclusive
-                let left_index = i - 1;
-                let right_index = j - 1;
-                let c_s = &s[left_index..right_index];
-
-                // literal token
-                let lit_tok = Token::Literal(String::from(c_s));
-                let lit_tok_matches = lit_tok.all_matches(s);
-                let lit_tok_matches_n = lit_tok_matches.len() as isize;
-                for (span_idx, span) in lit_tok_matches.iter().enumerate() {
-                    if span.start == i && span.end == j {
-                        let set = tokens.entry((i, j)).or_insert_with(BTreeSet::new);
-                        let span_idx = span_idx as isize;
-                        set.insert((lit_tok.clone(), Occurrence(span_idx + 1)));
-                        set.insert((lit_tok.clone(), Occurrence(span_idx - lit_tok_matches_n)));
-                        break; // don't need to look at more matches
+    // like regex_tokens, including the edges for the start and end of the string
+    fn anchored_regex_tokens(
+        s: &str,
+        custom_tokens: &[Token],
+    ) -> BTreeMap<Edge, BTreeSet<(Token, Occurrence)>> {
+        let mut tokens = Self::regex_tokens(s, custom_tokens);
+        tokens.insert((0, 1), BTreeSet::from([(Token::Start, Occurrence(1))]));
+        let n = s.chars().count();
+        tokens.insert(
+            (n + 1, n + 2),
+            BTreeSet::from([(Token::End, Occurrence(1))]),
+        );
+        tokens
+    }
+
+    // finds the boundary positions at every index of a string, from the adjacent edges of its
+    // graph: the tokens on the edges into a node end at its index, and the tokens on the edges out
+    // of it start there. Literal tokens are left out, because there are so many of them that pairs
+    // of them would make the sets huge, and they are specific enough to anchor positions by
+    // themselves.
+    pub fn boundaries(
+        s: &str,
+        custom_tokens: &[Token],
+    ) -> BTreeMap<usize, BTreeSet<(Token, Token, Occurrence)>> {
+        let mut ends: BTreeMap<Node, BTreeSet<Token>> = BTreeMap::new();
+        let mut starts: BTreeMap<Node, BTreeSet<Token>> = BTreeMap::new();
+        for ((vs, vf), toks) in Self::anchored_regex_tokens(s, custom_tokens) {
+            for (tok, _) in toks {
+                ends.entry(vf).or_default().insert(tok.clone());
+                starts.entry(vs).or_default().insert(tok);
+            }
+        }
+        // the nodes are the indices, so each list is in increasing order
+        let mut indices: BTreeMap<(Token, Token), Vec<Node>> = BTreeMap::new();
+        for (v, before) in &ends {
+            if let Some(after) = starts.get(v) {
+                for b in before {
+                    for a in after {
+                        indices.entry((b.clone(), a.clone())).or_default().push(*v);
                     }
                 }
             }
         }
+        let mut boundaries: BTreeMap<usize, BTreeSet<_>> = BTreeMap::new();
+        for ((before, after), vs) in indices {
+            let n = vs.len() as isize;
+            for (i, v) in vs.into_iter().enumerate() {
+                let set = boundaries.entry(v).or_default();
+                let i = i as isize;
+                set.insert((before.clone(), after.clone(), Occurrence(i + 1)));
+                set.insert((before.clone(), after.clone(), Occurrence(i - n)));
+            }
+        }
+        boundaries
+    }
 
-        Self { labels, tokens }
+    // finds the token match and boundary positions at every index of a string, for positions in
+    // strings that aren't in any column, like the intermediate strings of nested substrings; like
+    // boundaries, these leave out literal tokens, because there are so many of them
+    pub fn positions(s: &str, custom_tokens: &[Token]) -> BTreeMap<usize, BTreeSet<Position>> {
+        let mut positions: BTreeMap<usize, BTreeSet<Position>> = BTreeMap::new();
+        for ((vs, vf), toks) in Self::anchored_regex_tokens(s, custom_tokens) {
+            for (tok, occ) in toks {
+                let start = Position::Match(tok.clone(), occ, Direction::Start);
+                let end = Position::Match(tok, occ, Direction::End);
+                positions.entry(vs).or_default().insert(start);
+                positions.entry(vf).or_default().insert(end);
+            }
+        }
+        for (i, boundaries) in Self::boundaries(s, custom_tokens) {
+            let set = positions.entry(i).or_default();
+            for (before, after, k) in boundaries {
+                set.insert(Position::Boundary(before, after, k));
+            }
+        }
+        // the start of the Start token and the end of the End token aren't in the string
+        let n = s.chars().count();
+        positions.retain(|i, _| 0 < *i && *i <= n + 1);
+        positions
     }
 
     fn intersection(&self, other: &Self) -> Self {
@@ -134,23 +362,37 @@ impl InputDataGraph {
             })
         };
 
+        // the edges of the other graph, indexed by their tokens, so that only the pairs of edges
+        // that have tokens in common are compared; this is a hot spot, and most pairs of edges
+        // have nothing in common
+        let others: Vec<(&Edge, &TokenSet)> = other.tokens.iter().collect();
+        let mut index: HashMap<(TokenId, Occurrence), Vec<usize>> = HashMap::new();
+        for (j, (_, t2)) in others.iter().enumerate() {
+            for tok in t2.iter() {
+                index.entry(*tok).or_default().push(j);
+            }
+        }
+
         let mut tokens = BTreeMap::new();
         let mut nodes = HashSet::new();
         for ((v1s, v1f), t1) in &self.tokens {
-            for ((v2s, v2f), t2) in &other.tokens {
-                // this is a hot spot; checking if the sets are disjoint and only allocating a
-                // BTreeSet if they are not is faster than computing the intersection first and
-                // then checking if the intersection is empty
-                if !t1.is_disjoint(t2) {
-                    let intersection: BTreeSet<_> = t1.intersection(t2).cloned().collect();
-                    let vs = number(*v1s, *v2s);
-                    nodes.insert(vs);
-                    let vf = number(*v1f, *v2f);
-                    nodes.insert(vf);
-                    let e = (vs, vf);
-                    tokens.insert(e, intersection);
+            // the intersections with the edges of the other graph, in the order of the edges;
+            // going through t1 in order keeps each intersection sorted
+            let mut intersections: BTreeMap<usize, TokenSet> = BTreeMap::new();
+            for tok in t1 {
+                for j in index.get(tok).into_iter().flatten() {
+                    intersections.entry(*j).or_default().push(*tok);
                 }
             }
+            for (j, intersection) in intersections {
+                let (v2s, v2f) = others[j].0;
+                let vs = number(*v1s, *v2s);
+                nodes.insert(vs);
+                let vf = number(*v1f, *v2f);
+                nodes.insert(vf);
+                let e = (vs, vf);
+                tokens.insert(e, intersection);
+            }
         }
 
         let mut labels = BTreeMap::new();
@@ -165,12 +407,21 @@ impl InputDataGraph {
             }
         }
 
-        Self { labels, tokens }
+        Self {
+            labels,
+            tokens,
+            interner: Interner::default(),
+            rows: Vec::new(),
+            custom_tokens: Vec::new(),
+        }
     }
 
+    // the union of graphs with their own interners, with the tokens interned again in the
+    // interner of the union
     fn union(graphs: impl Iterator<Item = Self>) -> Self {
         let mut labels = BTreeMap::new();
         let mut tokens = BTreeMap::new();
+        let mut interner = Interner::default();
 
         let mut curr = 0;
         for graph in graphs {
@@ -186,13 +437,59 @@ impl InputDataGraph {
             for (n, l) in graph.labels {
                 labels.insert(number(n), l);
             }
-            // copy tokens, with renumbering
+            // copy tokens, with renumbering and reinterning
+            let ids: Vec<TokenId> = graph
+                .interner
+                .tokens
+                .into_iter()
+                .map(|tok| interner.intern(tok))
+                .collect();
             for ((v1, v2), t) in graph.tokens {
+                let mut t: TokenSet = t
+                    .into_iter()
+                    .map(|(id, k)| (ids[id.0 as usize], k))
+                    .collect();
+                t.sort_unstable();
                 tokens.insert((number(v1), number(v2)), t);
             }
         }
 
-        Self { labels, tokens }
+        Self {
+            labels,
+            tokens,
+            interner,
+            rows: Vec::new(),
+            custom_tokens: Vec::new(),
+        }
+    }
+
+    // the tokens on an edge with their occurrences, in the order of the tokens; edges that aren't
+    // in the graph have no tokens
+    pub fn edge_tokens(&self, edge: &Edge) -> BTreeSet<(&Token, Occurrence)> {
+        self.tokens
+            .get(edge)
+            .into_iter()
+            .flatten()
+            .map(|(id, k)| (self.interner.token(*id), *k))
+            .collect()
+    }
+
+    // computes the length of every string in the graph; the End token always survives
+    // intersection, and the node it starts at is one past the end of the string
+    pub fn lengths(&self) -> BTreeMap<Id, usize> {
+        let mut lengths = BTreeMap::new();
+        let end = match self.interner.id(&Token::End) {
+            Some(id) => (id, Occurrence(1)),
+            None => return lengths,
+        };
+        for ((vs, _), toks) in &self.tokens {
+            if toks.binary_search(&end).is_ok() {
+                for (id, si) in &self.labels[vs] {
+                    lengths.insert(*id, si.0 - 1);
+                }
+            }
+        }
+        lengths
     }
 
     pub fn distances(&self) -> HashMap<Edge, usize> {
@@ -250,14 +547,35 @@ impl InputDataGraph {
 }
 
 #[cfg(test)]
+#[allow(clippy::needless_borrow, clippy::useless_vec)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn lengths() {
+        let strs = vec![vec!["1 lb", ""], vec!["23 g", "x"], vec!["4 oz", "yz"]];
+        let graph = InputDataGraph::new(&strs, &[]);
+        let lengths = graph.lengths();
+        assert_eq!(lengths.len(), 6);
+        assert_eq!(lengths[&Id::new(0, 0)], 4);
+        assert_eq!(lengths[&Id::new(1, 0)], 4);
+        assert_eq!(lengths[&Id::new(0, 1)], 0);
+        assert_eq!(lengths[&Id::new(2, 1)], 2);
+    }
+
+    // the graph of a single string, with its own interner
+    fn from_str(s: &str, id: Id) -> InputDataGraph {
+        let mut interner = Interner::default();
+        let mut graph = InputDataGraph::from_str(s, id, &[], &mut Interning::Add(&mut interner));
+        graph.interner = interner;
+        graph
+    }
+
     #[test]
     fn generate_from_string_1() {
         // from Figure 6 in BlinkFill VLDB '16 paper
         let s = "1 lb";
-        let graph = InputDataGraph::from_str(&s, Id::new(0, 0));
+        let graph = from_str(&s, Id::new(0, 0));
         // do some spot checks
         assert_eq!(graph.nodes().len(), 7);
         assert_eq!(graph.edges().len(), 12);
@@ -272,7 +590,8 @@ mod tests {
         ]
         .into_iter()
         .collect();
-        assert_eq!(graph.tokens.get(&(1, 2)).unwrap(), &toks);
+        let toks: BTreeSet<_> = toks.iter().map(|(tok, k)| (tok, *k)).collect();
+        assert_eq!(graph.edge_tokens(&(1, 2)), toks);
         // check size of a_35
         assert_eq!(graph.tokens.get(&(3, 5)).unwrap().len(), 12);
     }
@@ -280,7 +599,7 @@ mod tests {
     #[test]
     fn generate_from_string_2() {
         let s = "23 g";
-        let graph = InputDataGraph::from_str(&s, Id::new(0, 0));
+        let graph = from_str(&s, Id::new(0, 0));
         // do some spot checks
         assert_eq!(graph.nodes().len(), 7);
         assert_eq!(graph.edges().len(), 12);
@@ -301,35 +620,119 @@ mod tests {
         ]
         .into_iter()
         .collect();
-        assert_eq!(graph.tokens.get(&(4, 5)).unwrap(), &toks);
+        let toks: BTreeSet<_> = toks.iter().map(|(tok, k)| (tok, *k)).collect();
+        assert_eq!(graph.edge_tokens(&(4, 5)), toks);
+    }
+
+    #[test]
+    fn boundaries() {
+        let boundaries = InputDataGraph::boundaries("ab12cd34", &[]);
+        let between = |i| (Token::Digits, Token::Lowercase, Occurrence(i));
+        assert!(boundaries[&5].contains(&between(1)));
+        assert!(boundaries[&5].contains(&between(-1)));
+        assert!(!boundaries[&3].contains(&between(1)));
+        assert!(boundaries[&3].contains(&(Token::Lowercase, Token::Digits, Occurrence(1))));
+        assert!(boundaries[&3].contains(&(Token::Lowercase, Token::Digits, Occurrence(-2))));
+        assert!(boundaries[&9].contains(&(Token::Digits, Token::End, Occurrence(1))));
+        assert!(!boundaries.contains_key(&2));
+    }
+
+    #[test]
+    fn literal_tokens() {
+        // the same as searching for every substring separately
+        for s in ["abcabcab", "aaaa", "a-b-a-b", "été, étés"] {
+            let chars: Vec<char> = s.chars().collect();
+            let mut expected = BTreeMap::new();
+            for i in 0..chars.len() {
+                for j in i + 1..chars.len() + 1 {
+                    let token = Token::Literal(chars[i..j].iter().collect());
+                    let matches = token.all_matches(s);
+                    let n = matches.len() as isize;
+                    if let Some(k) = matches.iter().position(|m| m.start == i + 1) {
+                        let k = k as isize;
+                        let toks = BTreeSet::from([
+                            (token.clone(), Occurrence(k + 1)),
+                            (token.clone(), Occurrence(k - n)),
+                        ]);
+                        expected.insert((i + 1, j + 1), toks);
+                    }
+                }
+            }
+            let mut interner = Interner::default();
+            let tokens = InputDataGraph::literal_tokens(&chars, &mut Interning::Add(&mut interner));
+            let tokens: BTreeMap<_, BTreeSet<_>> = tokens
+                .into_iter()
+                .map(|(edge, toks)| {
+                    // the sets are sorted already
+                    assert!(toks.windows(2).all(|w| w[0] < w[1]));
+                    let toks = toks
+                        .into_iter()
+                        .map(|(id, k)| (interner.token(id).clone(), k));
+                    (edge, toks.collect())
+                })
+                .collect();
+            assert_eq!(tokens, expected);
+        }
+        // overlapping occurrences aren't matches
+        let mut interner = Interner::default();
+        let mut interning = Interning::Add(&mut interner);
+        let tokens = InputDataGraph::literal_tokens(&['a', 'a', 'a'], &mut interning);
+        assert!(tokens.contains_key(&(1, 3)));
+        assert!(!tokens.contains_key(&(2, 4)));
     }
 
     #[test]
     fn intersection() {
         // from Figure 9 in BlinkFill paper
-        let g1 = InputDataGraph::from_str("1 lb", Id::new(0, 0));
-        let g2 = InputDataGraph::from_str("23 g", Id::new(1, 0));
-        let graph = g1.intersection(&g2);
+        let mut interner = Interner::default();
+        let mut interning = Interning::Add(&mut interner);
+        let g1 = InputDataGraph::from_str("1 lb", Id::new(0, 0), &[], &mut interning);
+        let g2 = InputDataGraph::from_str("23 g", Id::new(1, 0), &[], &mut interning);
+        let mut graph = g1.intersection(&g2);
+        graph.interner = interner;
         assert_eq!(graph.nodes().len(), 6);
         assert_eq!(graph.edges().len(), 6);
         let token_lengths: BTreeSet<_> = graph.tokens.values().map(|v| v.len()).collect();
         assert_eq!(token_lengths, vec![4, 1, 10, 2].iter().cloned().collect());
-        let toks: BTreeSet<_> = vec![
-            (Token::Literal(String::from(" ")), Occurrence(1)),
-            (Token::Literal(String::from(" ")), Occurrence(-1)),
-            (Token::Whitespace, Occurrence(1)),
-            (Token::Whitespace, Occurrence(-1)),
-        ]
-        .into_iter()
-        .collect();
-        assert!(graph.tokens.values().any(|v| *v == toks));
+        // the intersections are sorted
+        assert!(graph
+            .tokens
+            .values()
+            .all(|v| v.windows(2).all(|w| w[0] < w[1])));
+        let space = Token::Literal(String::from(" "));
+        let toks = BTreeSet::from([
+            (&space, Occurrence(1)),
+            (&space, Occurrence(-1)),
+            (&Token::Whitespace, Occurrence(1)),
+            (&Token::Whitespace, Occurrence(-1)),
+        ]);
+        assert!(graph.edges().any(|e| graph.edge_tokens(e) == toks));
+    }
+
+    #[test]
+    fn lookup() {
+        // the other rows of a column only keep the tokens that the first row has
+        let mut interner = Interner::default();
+        let mut interning = Interning::Add(&mut interner);
+        InputDataGraph::from_str("1 lb", Id::new(0, 0), &[], &mut interning);
+        let mut interning = Interning::Lookup(&interner);
+        let mut graph = InputDataGraph::from_str("23 g", Id::new(1, 0), &[], &mut interning);
+        graph.interner = interner;
+        let space = Token::Literal(String::from(" "));
+        assert!(graph
+            .edge_tokens(&(3, 4))
+            .contains(&(&space, Occurrence(1))));
+        let digits = graph.edge_tokens(&(1, 3));
+        assert!(digits.contains(&(&Token::Digits, Occurrence(1))));
+        assert!(digits.iter().all(|(tok, _)| tok.literal().is_none()));
+        assert!(!graph.edges().any(|e| graph.edge_tokens(e).is_empty()));
     }
 
     #[test]
     fn union() {
         // our own made-up test case, a basic sanity-check
-        let g1 = InputDataGraph::from_str("1 lb", Id::new(0, 0));
-        let g2 = InputDataGraph::from_str("23 g", Id::new(1, 0));
+        let g1 = from_str("1 lb", Id::new(0, 0));
+        let g2 = from_str("23 g", Id::new(1, 0));
         let graphs = vec![g1, g2];
         let union = InputDataGraph::union(graphs.into_iter());
         assert_eq!(union.nodes().len(), 14);
@@ -339,7 +742,7 @@ mod tests {
     #[test]
     fn new_single_column() {
         let strs = vec![vec!["1 lb"], vec!["23 g"]];
-        let graph = InputDataGraph::new(&strs);
+        let graph = InputDataGraph::new(&strs, &[]);
         assert_eq!(graph.nodes().len(), 6);
         assert_eq!(graph.edges().len(), 6);
     }
@@ -347,7 +750,7 @@ mod tests {
     #[test]
     fn new_multi_column() {
         let strs = vec![vec!["1 lb", "1 lb"], vec!["1 lb", "23 g"]];
-        let graph = InputDataGraph::new(&strs);
+        let graph = InputDataGraph::new(&strs, &[]);
         assert_eq!(graph.nodes().len(), 7 + 6);
         assert_eq!(graph.edges().len(), 12 + 6);
     }
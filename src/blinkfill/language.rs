@@ -1,16 +1,216 @@
+use super::date::DateFormat;
+use super::number::{self, NumberFormat};
+use super::table::Table;
 use super::token::Token;
 use crate::private::Sealed;
 use crate::StringProgram;
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
+use std::iter;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A program learned by BlinkFill.
+///
+/// This is the type returned by [`learn`](super::learn). Most programs evaluate a single
+/// [`expression`](Program::expression). When the examples come in several formats that no single
+/// expression can handle, a program is conditional instead: it consists of several
+/// [`branches`](Program::branches), and runs the expression of the first branch whose
+/// [`Predicate`] holds for the input. This corresponds to `Switch((b1, e1), ..., (bn, en))` in the
+/// Flash Fill paper.
+///
+/// Outside this crate, a program can only be obtained by learning it, parsing it with `str::parse`
+/// or [`Program::parse_with`], or deserializing it with the `serde` feature, and it can't be
+/// modified afterwards. The parser and the deserializer validate everything that learning
+/// guarantees, such as nonzero occurrences, nonempty literal tokens, known token names, and valid
+/// number and date formats, so every `Program` is well-formed.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Program {
+    // never empty
+    branches: Vec<(Predicate, StringExpression)>,
+}
+
+impl Program {
+    pub(crate) fn new(expr: StringExpression) -> Self {
+        Self {
+            branches: vec![(Predicate::default(), expr)],
+        }
+    }
+
+    // a program with the given branches, unless there are none
+    pub(crate) fn switch(branches: Vec<(Predicate, StringExpression)>) -> Option<Self> {
+        if branches.is_empty() {
+            None
+        } else {
+            Some(Self { branches })
+        }
+    }
+
+    /// Returns the string expression that this program evaluates, unless it is conditional.
+    pub fn expression(&self) -> Option<&StringExpression> {
+        match self.branches.as_slice() {
+            [(pred, expr)] if pred.0.is_empty() => Some(expr),
+            _ => None,
+        }
+    }
+
+    /// Returns the branches of this program, in the order in which their predicates are checked.
+    ///
+    /// A program that isn't conditional has a single branch, whose predicate always holds.
+    pub fn branches(&self) -> &[(Predicate, StringExpression)] {
+        &self.branches
+    }
+}
+
+impl Sealed for Program {}
+
+impl StringProgram for Program {
+    fn run<S: AsRef<str>>(&self, row: &[S]) -> Option<String> {
+        let (_, expr) = self.branches.iter().find(|(pred, _)| pred.matches(row))?;
+        expr.run(row)
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(expr) = self.expression() {
+            return fmt::Display::fmt(expr, f);
+        }
+        f.write_str("Switch(")?;
+        for (i, (pred, expr)) in self.branches.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "({}, {})", pred, expr)?;
+        }
+        f.write_str(")")
+    }
+}
+
+/// A condition on the input that selects a branch of a conditional [`Program`].
+///
+/// A predicate is a conjunction of [`Condition`]s: it holds when all of them hold, so a predicate
+/// without any conditions always holds.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
+pub struct Predicate(pub(crate) Vec<Condition>);
+
+impl Predicate {
+    /// Returns the conditions that all have to hold for this predicate to hold.
+    pub fn conditions(&self) -> &[Condition] {
+        &self.0
+    }
+
+    pub(crate) fn matches<S: AsRef<str>>(&self, row: &[S]) -> bool {
+        self.0.iter().all(|c| c.matches(row))
+    }
+}
+
+impl fmt::Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.as_slice() {
+            [] => f.write_str("True"),
+            [c] => write!(f, "{}", c),
+            cs => {
+                f.write_str("And(")?;
+                for (i, c) in cs.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", c)?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+/// A condition that a column contains at least a certain number of matches of a token.
+///
+/// This corresponds to `Match(v, r, k)` in the Flash Fill paper.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Condition {
+    pub(crate) column: ColumnIndex,
+    pub(crate) token: Token,
+    pub(crate) count: usize,
+}
+
+impl Condition {
+    /// Returns the column that is matched against.
+    pub fn column(&self) -> ColumnIndex {
+        self.column
+    }
+
+    /// Returns the token that is matched.
+    pub fn token(&self) -> &Token {
+        &self.token
+    }
+
+    /// Returns the minimum number of matches for the condition to hold.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub(crate) fn matches<S: AsRef<str>>(&self, row: &[S]) -> bool {
+        match row.get(self.column.0) {
+            Some(s) => self.token.all_matches(s.as_ref()).len() >= self.count,
+            None => false,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Match({}, {}, {})", self.column, self.token, self.count)
+    }
+}
+
+/// A zero-based index of a column in the input row.
 #[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
-pub struct ColumnIndex(pub usize);
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
+pub struct ColumnIndex(pub(crate) usize);
+
+impl ColumnIndex {
+    /// Returns the zero-based column index.
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
 
+/// A concatenation of substring expressions.
+///
+/// This corresponds to `Concat(f1, ..., fn)` in the BlinkFill paper.
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub struct StringExpression(pub Vec<SubstringExpression>);
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
+pub struct StringExpression(pub(crate) Vec<SubstringExpression>);
+
+impl StringExpression {
+    /// Returns the substring expressions whose outputs are concatenated to produce the output.
+    pub fn parts(&self) -> &[SubstringExpression] {
+        &self.0
+    }
+}
 
 impl Sealed for StringExpression {}
 
+/// Formats the expression in the DSL from the BlinkFill paper.
+///
+/// For example, a program that extracts the initial of the first word followed by a period is
+/// written as `Concat(SubStr(col0, ConstPos(1), ConstPos(2)), Const("."))`.
+impl fmt::Display for StringExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Concat(")?;
+        for (i, e) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", e)?;
+        }
+        f.write_str(")")
+    }
+}
+
 impl StringProgram for StringExpression {
     fn run<S: AsRef<str>>(&self, row: &[S]) -> Option<String> {
         self.0.iter().try_fold(String::new(), |mut s, e| {
@@ -22,51 +222,453 @@ impl StringProgram for StringExpression {
     }
 }
 
+/// An expression that produces one part of the output of a [`StringExpression`].
 #[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
 pub enum SubstringExpression {
+    /// A constant string, independent of the input.
+    #[non_exhaustive]
     ConstantString(String),
+    /// The substring of a column between a start position and an end position.
+    #[non_exhaustive]
     Substring(ColumnIndex, Position, Position),
+    /// The substring of the output of another expression between a start position and an end
+    /// position, such as the last word of the part of a column between parentheses.
+    #[non_exhaustive]
+    SubstringOf(Box<SubstringExpression>, Position, Position),
+    /// The output of another expression, converted to a different case.
+    #[non_exhaustive]
+    Convert(Case, Box<SubstringExpression>),
+    /// The outputs of a body expression for every match of a token in a column, joined with a
+    /// separator.
+    ///
+    /// The body is run on each match as if the match were a row with a single column, so the body
+    /// refers to the match as `col0`.
+    #[non_exhaustive]
+    Loop(ColumnIndex, Token, Box<SubstringExpression>, String),
+    /// The output of another expression, looked up in a table.
+    #[non_exhaustive]
+    Lookup(Table, Box<SubstringExpression>),
+    /// The output of another expression, which must be a decimal number, in a different format.
+    #[non_exhaustive]
+    Number(NumberFormat, Box<SubstringExpression>),
+    /// The output of another expression, parsed as a date in one format and written in another.
+    #[non_exhaustive]
+    Date(DateFormat, DateFormat, Box<SubstringExpression>),
+    /// The value of an integer expression, in a number format.
+    #[non_exhaustive]
+    Integer(IntegerExpression, NumberFormat),
 }
 
 impl SubstringExpression {
+    /// Returns the string if this is a constant string expression.
+    pub fn constant(&self) -> Option<&str> {
+        match self {
+            SubstringExpression::ConstantString(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the column that this expression extracts a substring from, if any.
+    pub fn column(&self) -> Option<ColumnIndex> {
+        match self {
+            SubstringExpression::Substring(ci, _, _) => Some(*ci),
+            _ => None,
+        }
+    }
+
+    /// Returns the start and end positions if this is a substring expression, either of a column
+    /// or of the output of another expression.
+    pub fn positions(&self) -> Option<(&Position, &Position)> {
+        match self {
+            SubstringExpression::Substring(_, p_start, p_end)
+            | SubstringExpression::SubstringOf(_, p_start, p_end) => Some((p_start, p_end)),
+            _ => None,
+        }
+    }
+
+    /// Returns the expression that produces the string that the substring is taken from if this
+    /// is a substring of the output of another expression.
+    pub fn source(&self) -> Option<&SubstringExpression> {
+        match self {
+            SubstringExpression::SubstringOf(e, _, _) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Returns the case and the converted expression if this is a case conversion.
+    pub fn conversion(&self) -> Option<(Case, &SubstringExpression)> {
+        match self {
+            SubstringExpression::Convert(case, e) => Some((*case, e)),
+            _ => None,
+        }
+    }
+
+    /// Returns the table and the expression that produces the key if this is a table lookup.
+    pub fn lookup(&self) -> Option<(&Table, &SubstringExpression)> {
+        match self {
+            SubstringExpression::Lookup(table, e) => Some((table, e)),
+            _ => None,
+        }
+    }
+
+    /// Returns the format and the expression that produces the number if this is a number
+    /// formatting expression.
+    pub fn number_format(&self) -> Option<(&NumberFormat, &SubstringExpression)> {
+        match self {
+            SubstringExpression::Number(format, e) => Some((format, e)),
+            _ => None,
+        }
+    }
+
+    /// Returns the format that the date is parsed in, the format that it is written in, and the
+    /// expression that produces the date if this is a date reformatting expression.
+    pub fn date_formats(&self) -> Option<(&DateFormat, &DateFormat, &SubstringExpression)> {
+        match self {
+            SubstringExpression::Date(input, output, e) => Some((input, output, e)),
+            _ => None,
+        }
+    }
+
+    /// Returns the integer expression and the format that its value is written in if this is an
+    /// integer expression.
+    pub fn integer(&self) -> Option<(&IntegerExpression, &NumberFormat)> {
+        match self {
+            SubstringExpression::Integer(e, format) => Some((e, format)),
+            _ => None,
+        }
+    }
+
+    /// Returns the column, the token, the body, and the separator if this is a loop.
+    pub fn loop_parts(&self) -> Option<(ColumnIndex, &Token, &SubstringExpression, &str)> {
+        match self {
+            SubstringExpression::Loop(ci, token, body, sep) => Some((*ci, token, body, sep)),
+            _ => None,
+        }
+    }
+
+    /// Runs the expression on the given list of strings.
+    ///
+    /// Returns `None` if the expression fails on this input.
     pub fn run<S: AsRef<str>>(&self, row: &[S]) -> Option<String> {
         match self {
             SubstringExpression::ConstantString(s) => Some(s.clone()),
             SubstringExpression::Substring(ci, p_start, p_end) => {
+                substring(row.get(ci.0)?.as_ref(), p_start, p_end)
+            }
+            SubstringExpression::SubstringOf(e, p_start, p_end) => {
+                substring(&e.run(row)?, p_start, p_end)
+            }
+            SubstringExpression::Convert(case, e) => e.run(row).map(|s| case.apply(&s)),
+            SubstringExpression::Loop(ci, token, body, sep) => {
                 let s = row.get(ci.0)?.as_ref();
-                let p_start = p_start.run(s)?;
-                let p_end = p_end.run(s)?;
-                if p_start.0 >= p_end.0 {
-                    return None;
+                let parts = token
+                    .all_matches(s)
+                    .iter()
+                    .map(|m| body.run(&[slice_chars(s, m.start, m.end)?]))
+                    .collect::<Option<Vec<String>>>()?;
+                Some(parts.join(sep))
+            }
+            SubstringExpression::Lookup(table, e) => {
+                e.run(row).and_then(|key| table.get(&key).map(String::from))
+            }
+            SubstringExpression::Number(format, e) => e.run(row).and_then(|n| format.apply(&n)),
+            SubstringExpression::Date(input, output, e) => e
+                .run(row)
+                .and_then(|s| input.parse(&s))
+                .and_then(|date| output.format(&date)),
+            SubstringExpression::Integer(e, format) => {
+                e.run(row).and_then(|n| format.apply(&n.to_string()))
+            }
+        }
+    }
+}
+
+fn substring(s: &str, p_start: &Position, p_end: &Position) -> Option<String> {
+    let p_start = p_start.run(s)?;
+    let p_end = p_end.run(s)?;
+    if p_start.0 >= p_end.0 {
+        return None;
+    }
+    slice_chars(s, p_start.0, p_end.0).map(String::from)
+}
+
+impl fmt::Display for SubstringExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubstringExpression::ConstantString(s) => {
+                f.write_str("Const(")?;
+                write_quoted(f, s)?;
+                f.write_str(")")
+            }
+            SubstringExpression::Substring(ci, p_start, p_end) => {
+                write!(f, "SubStr({}, {}, {})", ci, p_start, p_end)
+            }
+            SubstringExpression::SubstringOf(e, p_start, p_end) => {
+                write!(f, "SubStr({}, {}, {})", e, p_start, p_end)
+            }
+            SubstringExpression::Convert(case, e) => write!(f, "{}({})", case, e),
+            SubstringExpression::Loop(ci, token, body, sep) => {
+                write!(f, "Loop({}, {}, {}, ", ci, token, body)?;
+                write_quoted(f, sep)?;
+                f.write_str(")")
+            }
+            SubstringExpression::Lookup(table, e) => {
+                f.write_str("Lookup(")?;
+                write_quoted(f, table.name())?;
+                write!(f, ", {})", e)
+            }
+            SubstringExpression::Number(format, e) => write!(f, "Number({}, {})", e, format),
+            SubstringExpression::Date(input, output, e) => {
+                write!(f, "Date({}, ", e)?;
+                write_quoted(f, &input.to_string())?;
+                f.write_str(", ")?;
+                write_quoted(f, &output.to_string())?;
+                f.write_str(")")
+            }
+            SubstringExpression::Integer(e, format) => write!(f, "Integer({}, {})", e, format),
+        }
+    }
+}
+
+/// An expression that produces an integer, for arithmetic on numbers in the input.
+#[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub enum IntegerExpression {
+    /// The output of a substring expression, which must be an integer, such as "04" or "-12".
+    #[non_exhaustive]
+    Parse(Box<SubstringExpression>),
+    /// The value of another integer expression plus a constant.
+    #[non_exhaustive]
+    Add(Box<IntegerExpression>, i64),
+}
+
+impl IntegerExpression {
+    /// Returns the expression whose output is parsed if this parses an integer.
+    pub fn parsed(&self) -> Option<&SubstringExpression> {
+        match self {
+            IntegerExpression::Parse(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Returns the integer expression and the constant that is added to it if this is an
+    /// addition.
+    pub fn sum(&self) -> Option<(&IntegerExpression, i64)> {
+        match self {
+            IntegerExpression::Add(e, k) => Some((e, *k)),
+            _ => None,
+        }
+    }
+
+    /// Runs the expression on the given list of strings.
+    ///
+    /// Returns `None` if the expression fails on this input, including when the result overflows.
+    pub fn run<S: AsRef<str>>(&self, row: &[S]) -> Option<i64> {
+        match self {
+            IntegerExpression::Parse(e) => e.run(row).and_then(|s| number::parse_integer(&s)),
+            IntegerExpression::Add(e, k) => e.run(row).and_then(|n| n.checked_add(*k)),
+        }
+    }
+}
+
+impl fmt::Display for IntegerExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegerExpression::Parse(e) => write!(f, "Parse({})", e),
+            IntegerExpression::Add(e, k) => write!(f, "Add({}, {})", e, k),
+        }
+    }
+}
+
+/// A letter case that a string can be converted to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Case {
+    /// All letters in upper case, such as "ALYSSA P. HACKER".
+    Upper,
+    /// All letters in lower case, such as "alyssa p. hacker".
+    Lower,
+    /// The first letter of every word in upper case and the other letters in lower case, such as
+    /// "Alyssa P. Hacker". A word is a run of alphabetic characters.
+    Proper,
+}
+
+impl Case {
+    pub(crate) fn apply(self, s: &str) -> String {
+        match self {
+            Case::Upper => s.to_uppercase(),
+            Case::Lower => s.to_lowercase(),
+            Case::Proper => {
+                let mut result = String::with_capacity(s.len());
+                let mut in_word = false;
+                for c in s.chars() {
+                    if !c.is_alphabetic() {
+                        result.push(c);
+                    } else if in_word {
+                        result.extend(c.to_lowercase());
+                    } else {
+                        result.extend(c.to_uppercase());
+                    }
+                    in_word = c.is_alphabetic();
                 }
-                Some(String::from(&s[p_start.0 - 1..p_end.0 - 1]))
+                result
             }
         }
     }
 }
 
-// a one-based string index
+impl fmt::Display for Case {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Case::Upper => f.write_str("Upper"),
+            Case::Lower => f.write_str("Lower"),
+            Case::Proper => f.write_str("Proper"),
+        }
+    }
+}
+
+impl fmt::Display for ColumnIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "col{}", self.0)
+    }
+}
+
+// writes a string literal, escaping quotes, backslashes, and control characters
+pub(crate) fn write_quoted(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+// a one-based string index, counting characters
 #[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
 pub struct StringIndex(pub usize);
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
-pub struct Occurrence(pub isize);
+// the part of s from the one-based character index start up to end (exclusive), or None if the
+// indices aren't in the string; the start and end tokens have matches just outside of the string,
+// so this can't assume that the indices are valid
+pub(crate) fn slice_chars(s: &str, start: usize, end: usize) -> Option<&str> {
+    if start == 0 || start > end {
+        return None;
+    }
+    let mut offsets = s.char_indices().map(|(i, _)| i).chain(iter::once(s.len()));
+    let l = offsets.nth(start - 1)?;
+    let r = if end == start {
+        l
+    } else {
+        offsets.nth(end - start - 1)?
+    };
+    Some(&s[l..r])
+}
+
+// the number of characters before each byte offset of s, for converting the byte offsets that
+// searching gives into character indices; the entries for offsets inside of a character are the
+// index of that character
+pub(crate) fn char_offsets(s: &str) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(s.len() + 1);
+    let mut n = 0;
+    for c in s.chars() {
+        offsets.extend(iter::repeat(n).take(c.len_utf8()));
+        n += 1;
+    }
+    offsets.push(n);
+    offsets
+}
+
+/// A nonzero occurrence count.
+///
+/// Positive values count from the start of the string (1 is the first occurrence), and negative
+/// values count from the end of the string (-1 is the last occurrence).
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord, Hash)]
+pub struct Occurrence(pub(crate) isize);
 
 impl Occurrence {
-    pub fn weight(&self) -> isize {
+    /// Returns the occurrence count.
+    pub fn get(&self) -> isize {
+        self.0
+    }
+
+    pub(crate) fn weight(&self) -> isize {
         // prefer occurrences closer to ends
         -self.0.abs()
     }
 }
 
+impl fmt::Display for Occurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A position in a string.
 #[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
 pub enum Position {
+    /// The start or end of the k-th match of a token.
+    #[non_exhaustive]
     Match(Token, Occurrence, Direction),
+    /// A constant index into the string, counting characters from the start or the end.
+    #[non_exhaustive]
     ConstantPosition(Occurrence),
+    /// The k-th index where a match of the first token ends and a match of the second token
+    /// starts.
+    ///
+    /// This corresponds to `pos(r1, r2, c)` in the Flash Fill paper, with a single token on either
+    /// side.
+    #[non_exhaustive]
+    Boundary(Token, Token, Occurrence),
 }
 
 impl Position {
-    fn run(&self, s: &str) -> Option<StringIndex> {
+    /// Returns the token if this is a token match position.
+    pub fn token(&self) -> Option<&Token> {
+        match self {
+            Position::Match(token, _, _) => Some(token),
+            _ => None,
+        }
+    }
+
+    /// Returns the tokens before and after the index if this is a boundary position.
+    pub fn boundary(&self) -> Option<(&Token, &Token)> {
+        match self {
+            Position::Boundary(before, after, _) => Some((before, after)),
+            _ => None,
+        }
+    }
+
+    /// Returns the occurrence of the token match or boundary, or the index for a constant
+    /// position.
+    pub fn occurrence(&self) -> Occurrence {
+        match self {
+            Position::Match(_, k, _) => *k,
+            Position::ConstantPosition(k) => *k,
+            Position::Boundary(_, _, k) => *k,
+        }
+    }
+
+    /// Returns the side of the token match if this is a token match position.
+    pub fn direction(&self) -> Option<Direction> {
+        match self {
+            Position::Match(_, _, dir) => Some(*dir),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn run(&self, s: &str) -> Option<StringIndex> {
         match self {
             Position::Match(token, k, dir) => {
                 let k = k.0;
@@ -85,7 +687,7 @@ impl Position {
             }
             Position::ConstantPosition(k) => {
                 let k = k.0;
-                let n = s.len() as isize;
+                let n = s.chars().count() as isize;
                 let k = if k > 0 { k } else { n + k + 1 };
                 if !(0 < k && k <= n + 1) {
                     None
@@ -93,17 +695,69 @@ impl Position {
                     Some(StringIndex(k as usize))
                 }
             }
+            Position::Boundary(before, after, k) => {
+                let indices = boundaries(before, after, s);
+                let n = indices.len() as isize;
+                let k = if k.0 > 0 { k.0 - 1 } else { n + k.0 };
+                if !(0 <= k && k < n) {
+                    return None;
+                }
+                Some(StringIndex(indices[k as usize]))
+            }
+        }
+    }
+}
+
+// the indices where a match of one token ends and a match of another starts, in increasing order
+pub(crate) fn boundaries(before: &Token, after: &Token, s: &str) -> Vec<usize> {
+    // the matches of a token don't overlap, so their starts and ends are both increasing, and the
+    // common indices are found by walking through the two lists together
+    let ends = before.all_matches(s);
+    let starts = after.all_matches(s);
+    let mut starts = starts.iter().map(|m| m.start).peekable();
+    let mut common = Vec::new();
+    for end in ends.iter().map(|m| m.end) {
+        while starts.next_if(|&start| start < end).is_some() {}
+        if starts.next_if_eq(&end).is_some() {
+            common.push(end);
+        }
+    }
+    common
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Position::Match(token, k, dir) => write!(f, "Pos({}, {}, {})", token, k, dir),
+            Position::ConstantPosition(k) => write!(f, "ConstPos({})", k),
+            Position::Boundary(before, after, k) => {
+                write!(f, "BoundaryPos({}, {}, {})", before, after, k)
+            }
         }
     }
 }
 
+/// Which side of a token match a position refers to.
 #[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Direction {
+    /// The index of the first character of the match.
     Start,
+    /// The index just past the last character of the match.
     End,
 }
 
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Start => f.write_str("Start"),
+            Direction::End => f.write_str("End"),
+        }
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::useless_vec)]
 mod tests {
     use super::*;
     use Direction::*;
@@ -115,6 +769,12 @@ mod tests {
         assert_eq!(res, String::from(expected));
     }
 
+    // checks how the program is printed, and that it parses back from what was printed
+    fn assert_display(p: &StringExpression, expected: &str) {
+        assert_eq!(p.to_string(), expected);
+        assert_eq!(expected.parse::<StringExpression>().as_ref(), Ok(p));
+    }
+
     #[test]
     fn extract_country() {
         let p = StringExpression(vec![Substring(
@@ -131,6 +791,57 @@ mod tests {
         assert_eval_single(&p, "Newark, United States", "United States");
     }
 
+    #[test]
+    fn boundary_positions() {
+        // the unit right after the last number
+        let p = StringExpression(vec![Substring(
+            ColumnIndex(0),
+            Boundary(Token::Digits, Token::Lowercase, Occurrence(-1)),
+            Match(Token::Lowercase, Occurrence(-1), End),
+        )]);
+        assert_eval_single(&p, "box 2 of 12kg", "kg");
+        assert_eval_single(&p, "3lb", "lb");
+        assert_eq!(p.run(&["2 of 12"]), None);
+        assert_display(
+            &p,
+            "Concat(SubStr(col0, BoundaryPos(Digits, lowercase, -1), Pos(lowercase, -1, End)))",
+        );
+        let s = "1a 22bb3 c4d5";
+        assert_eq!(
+            boundaries(&Token::Digits, &Token::Lowercase, s),
+            vec![2, 6, 12]
+        );
+        assert_eq!(
+            boundaries(&Token::Lowercase, &Token::Digits, s),
+            vec![8, 11, 13]
+        );
+        assert_eq!(boundaries(&Token::Digits, &Token::Whitespace, s), vec![9]);
+        assert!(boundaries(&Token::Digits, &Token::Digits, s).is_empty());
+    }
+
+    #[test]
+    fn substring_of_substring() {
+        // the last word between the parentheses
+        let between = Substring(
+            ColumnIndex(0),
+            Match(Token::Literal(String::from("(")), Occurrence(1), End),
+            Match(Token::Literal(String::from(")")), Occurrence(1), Start),
+        );
+        let p = StringExpression(vec![SubstringOf(
+            Box::new(between),
+            Match(Token::Alphabets, Occurrence(-1), Start),
+            Match(Token::End, Occurrence(1), Start),
+        )]);
+        assert_eval_single(&p, "Bob (Mercy Clinic) Wing B", "Clinic");
+        assert_eval_single(&p, "(Boston General Hospital)", "Hospital");
+        assert_eq!(p.run(&["Bob (42) Wing B"]), None);
+        assert_display(
+            &p,
+            "Concat(SubStr(SubStr(col0, Pos(\"(\", 1, End), Pos(\")\", 1, Start)), \
+             Pos(Alphabets, -1, Start), Pos(EndT, 1, Start)))",
+        );
+    }
+
     #[test]
     fn extract_initials() {
         let p = StringExpression(vec![
@@ -198,5 +909,174 @@ mod tests {
             Match(Token::Literal(String::from("|")), Occurrence(1), Start),
         )]);
         assert_eval_single(&p, "xzHello|asdofij", "Hello");
+        // indices count characters
+        assert_eval_single(&p, "éüĦéllo|ñ", "Ħéllo");
+        let p = StringExpression(vec![Substring(
+            ColumnIndex(0),
+            ConstantPosition(Occurrence(-2)),
+            Match(Token::End, Occurrence(1), Start),
+        )]);
+        assert_eval_single(&p, "東京都港区", "港区");
+        // the start of the Start token and the end of the End token aren't in the string
+        let p = StringExpression(vec![Substring(
+            ColumnIndex(0),
+            Match(Token::Start, Occurrence(1), Start),
+            Match(Token::End, Occurrence(1), End),
+        )]);
+        assert_eq!(p.run(&["é"]), None);
+    }
+
+    #[test]
+    fn convert_case() {
+        assert_eq!(Case::Upper.apply("ab123 straße"), "AB123 STRASSE");
+        assert_eq!(Case::Lower.apply("ALYSSA P. Hacker"), "alyssa p. hacker");
+        assert_eq!(
+            Case::Proper.apply("o'NEIL mcdonald-SMITH"),
+            "O'Neil Mcdonald-Smith"
+        );
+        let p = StringExpression(vec![
+            Convert(
+                Case::Proper,
+                Box::new(Substring(
+                    ColumnIndex(0),
+                    Match(Token::Whitespace, Occurrence(1), End),
+                    Match(Token::End, Occurrence(1), Start),
+                )),
+            ),
+            ConstantString(String::from(", ")),
+            Convert(
+                Case::Upper,
+                Box::new(Substring(
+                    ColumnIndex(0),
+                    Match(Token::Start, Occurrence(1), End),
+                    ConstantPosition(Occurrence(2)),
+                )),
+            ),
+            ConstantString(String::from(".")),
+        ]);
+        assert_eval_single(&p, "alyssa hacker", "Hacker, A.");
+        assert_eval_single(&p, "ben BITDIDDLE", "Bitdiddle, B.");
+        assert_display(
+            &p,
+            "Concat(Proper(SubStr(col0, Pos(Whitespace, 1, End), Pos(EndT, 1, Start))), \
+             Const(\", \"), Upper(SubStr(col0, Pos(StartT, 1, End), ConstPos(2))), Const(\".\"))",
+        );
+    }
+
+    #[test]
+    fn loop_over_matches() {
+        // the initial of every word, followed by a period
+        let initial = Substring(
+            ColumnIndex(0),
+            ConstantPosition(Occurrence(1)),
+            ConstantPosition(Occurrence(2)),
+        );
+        let p = StringExpression(vec![
+            Loop(
+                ColumnIndex(0),
+                Token::Alphabets,
+                Box::new(initial),
+                String::from("."),
+            ),
+            ConstantString(String::from(".")),
+        ]);
+        assert_eval_single(&p, "Alyssa P. Hacker", "A.P.H.");
+        assert_eval_single(&p, "ben bitdiddle", "b.b.");
+        assert_eval_single(&p, "1984", ".");
+        assert_display(
+            &p,
+            "Concat(Loop(col0, Alphabets, SubStr(col0, ConstPos(1), ConstPos(2)), \".\"), \
+             Const(\".\"))",
+        );
+        // remove all the spaces
+        let whole = Substring(
+            ColumnIndex(0),
+            ConstantPosition(Occurrence(1)),
+            Match(Token::End, Occurrence(1), Start),
+        );
+        let p = StringExpression(vec![Loop(
+            ColumnIndex(0),
+            Token::NotWhitespace,
+            Box::new(whole),
+            String::new(),
+        )]);
+        assert_eval_single(&p, " 12 34  56 ", "123456");
+        // fails if the body fails on any match
+        let p = StringExpression(vec![Loop(
+            ColumnIndex(0),
+            Token::Alphabets,
+            Box::new(Substring(
+                ColumnIndex(0),
+                ConstantPosition(Occurrence(1)),
+                ConstantPosition(Occurrence(3)),
+            )),
+            String::new(),
+        )]);
+        assert_eq!(p.run(&["ab c"]), None);
+    }
+
+    #[test]
+    fn integer_arithmetic() {
+        let episode = Substring(
+            ColumnIndex(0),
+            Match(Token::Literal(String::from("E")), Occurrence(1), End),
+            Match(Token::End, Occurrence(1), Start),
+        );
+        let next = IntegerExpression::Add(Box::new(IntegerExpression::Parse(Box::new(episode))), 1);
+        let format = NumberFormat {
+            width: 2,
+            decimals: None,
+            separator: None,
+        };
+        let p = StringExpression(vec![
+            ConstantString(String::from("E")),
+            Integer(next, format),
+        ]);
+        assert_eval_single(&p, "S01E04", "E05");
+        assert_eval_single(&p, "S01E99", "E100");
+        assert_eq!(p.run(&["S01E4a"]), None);
+        assert_display(
+            &p,
+            "Concat(Const(\"E\"), Integer(Add(Parse(SubStr(col0, Pos(\"E\", 1, End), \
+             Pos(EndT, 1, Start))), 1), 2, Keep, \"\"))",
+        );
+    }
+
+    #[test]
+    fn display() {
+        let p = StringExpression(vec![
+            Substring(
+                ColumnIndex(0),
+                Match(Token::ProperCase, Occurrence(1), Start),
+                Match(Token::Caps, Occurrence(-1), End),
+            ),
+            ConstantString(String::from("'")),
+            Substring(
+                ColumnIndex(1),
+                ConstantPosition(Occurrence(3)),
+                Match(Token::End, Occurrence(1), Start),
+            ),
+        ]);
+        assert_display(
+            &p,
+            "Concat(SubStr(col0, Pos(ProperCase, 1, Start), Pos(CAPS, -1, End)), Const(\"'\"), \
+             SubStr(col1, ConstPos(3), Pos(EndT, 1, Start)))",
+        );
+    }
+
+    #[test]
+    fn display_escapes_strings() {
+        let p = StringExpression(vec![
+            Substring(
+                ColumnIndex(0),
+                Match(Token::Literal(String::from("\"\\")), Occurrence(1), End),
+                Match(Token::Literal(String::from("\t")), Occurrence(1), Start),
+            ),
+            ConstantString(String::from("\n\u{7}é")),
+        ]);
+        assert_display(
+            &p,
+            r#"Concat(SubStr(col0, Pos("\"\\", 1, End), Pos("\t", 1, Start)), Const("\n\u{7}é"))"#,
+        );
     }
 }
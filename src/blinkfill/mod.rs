@@ -4,37 +4,88 @@
 //! This crate implements [BlinkFill (Singh '16, in Proc. VLDB)][blinkfill-paper], an algorithm to
 //! learn syntactic string transformations from input-output examples along with unpaired examples.
 //! The learned programs belong to a fairly restricted language. The language includes features
-//! like extracting substrings based on token matches, but it does not include features like loops.
-//! For this reason, there are certain transformations on strings that cannot be learned by
-//! BlinkFill, such as "remove all the spaces from a string". Furthermore, BlinkFill programs are
-//! purely syntactic transformations, so semantic transformations like mapping "3" to "March"
-//! cannot be learned. Nevertheless, BlinkFill works well on many practical string transformation
-//! tasks.
+//! like extracting substrings based on token matches (possibly from other substrings), converting
+//! them to upper, lower, or proper case, reformatting numbers (such as padding "7" to "007" or
+//! rounding "3.14159" to "3.14") and dates (such as turning "Jul 3, 2021" into "2021-07-03"),
+//! adding constants to integers, and looping over every match of a token, so transformations like
+//! "remove all the spaces from a string" or "take the initial of every word" can be learned.
+//! However, BlinkFill programs are purely syntactic transformations, so semantic transformations
+//! like mapping "3" to "March" cannot be learned unless the mapping is given as a lookup
+//! [`Table`]. Nevertheless, BlinkFill works well on many practical string transformation tasks.
+//!
+//! Learned programs are returned as a [`Program`], which can be run on new inputs through the
+//! [`StringProgram`] trait or inspected through its
+//! [`StringExpression`]. Programs are displayed in the DSL used by the BlinkFill paper, such as
+//! `Concat(SubStr(col0, Pos(ProperCase, 1, Start), ConstPos(2)), Const("."))`, and they can be
+//! parsed back from this format with [`str::parse`]. With the `serde` feature enabled, programs
+//! can be serialized and deserialized, so a program can be learned once and applied later.
+//! Positions in strings count characters (Unicode scalar values) rather than bytes, so programs
+//! mean the same thing on every row of non-ASCII text, and running a program on any input either
+//! produces a string or fails without panicking. With the `rayon` feature enabled, the rows of
+//! the examples are processed in parallel, which speeds up learning from many unpaired rows; the
+//! learned programs are the same with and without it.
+//!
+//! Examples are often ambiguous. [`learn_all`] returns the whole set of programs consistent with
+//! the examples. It can enumerate the highest-ranked alternatives, suggest the unpaired row that
+//! would be most informative to add as an example, and estimate how confident the best program is
+//! in each of its outputs. A [`Learner`] can be used to configure learning, such as how programs
+//! are ranked.
+//!
+//! When the inputs come in several formats that no single expression can handle, [`learn`] falls
+//! back to a conditional program, which picks a branch based on [`Predicate`]s over the input
+//! columns, as in the `Switch` construct of the Flash Fill paper.
 //!
 //! [blinkfill-paper]: http://www.vldb.org/pvldb/vol9/p816-singh.pdf
 
-use crate::StringProgram;
-
 mod dag;
+mod date;
 mod input_data_graph;
 mod language;
+mod number;
+mod parse;
+mod sample;
+#[cfg(feature = "serde")]
+mod serialize;
+mod suffix_array;
+mod switch;
+mod table;
 mod token;
 
+use crate::{private, StringProgram};
 use dag::Dag;
 use input_data_graph::InputDataGraph;
 
+use std::cmp::Reverse;
+use std::error::Error;
+use std::fmt;
+
+pub use date::DateFormat;
+pub use language::{
+    Case, ColumnIndex, Condition, Direction, IntegerExpression, Occurrence, Position, Predicate,
+    Program, StringExpression, SubstringExpression,
+};
+pub use number::NumberFormat;
+pub use parse::ParseError;
+pub use table::Table;
+pub use token::{CustomToken, Token};
+
 /// Learns a string program using the BlinkFill algorithm.
 ///
 /// BlinkFill learns from input-output examples as well as unpaired examples, where only the input
 /// is available and the output is unknown. Performance decreases as the number of unpaired
 /// examples (as well as input-output examples) grows, so if a large number of unpaired examples
-/// are available, it is recommended to use a random subset of them for inference.
+/// are available, [`Learner::max_unpaired`] can be used to learn from a sample of them.
 ///
 /// Duplication should be avoided between the unpaired inputs and paired examples. All of the
 /// examples should have the same number of columns.
 ///
-/// This function returns `None` if no string program satisfying all of the input-output examples
-/// can be found.
+/// If no single string expression is consistent with all of the examples, this tries to learn a
+/// conditional program instead: the examples are partitioned into groups that each have a
+/// consistent expression, and a [`Predicate`] is learned that tells the inputs of each group
+/// apart, so each group gets its own branch.
+///
+/// This function returns an error if the examples are malformed or if no string program satisfying
+/// all of the input-output examples can be found; see [`LearnError`] for details.
 ///
 /// # Example: extracting area code from phone numbers in mixed formats
 ///
@@ -57,51 +108,470 @@ use input_data_graph::InputDataGraph;
 /// let examples = &[(vec!["(123)-456-7890"],  "123"),
 ///                  (vec!["+1 234-567-8901"], "234")];
 ///
-/// let prog = blinkfill::learn(unpaired, examples)?;
+/// let prog = blinkfill::learn(unpaired, examples).ok()?;
 ///
 /// let result = prog.run(&["(617) 253-1337"])?;
 /// assert_eq!(result, "617");
 /// # Some(())
 /// # }
 /// ```
+pub fn learn<S0, S1, S2>(
+    unpaired: &[Vec<S0>],
+    examples: &[(Vec<S1>, S2)],
+) -> Result<Program, LearnError>
+where
+    S0: AsRef<str>,
+    S1: AsRef<str>,
+    S2: AsRef<str>,
+{
+    Learner::new().learn(unpaired, examples)
+}
+
+/// Learns the set of all string programs consistent with the examples.
 ///
-/// # Panics
+/// This takes the same arguments as [`learn`], but rather than picking a single program, it
+/// returns a [`ProgramSet`], from which the highest-ranked programs can be enumerated. This is
+/// useful for showing alternative interpretations of the examples to the user. Unlike [`learn`],
+/// this doesn't learn conditional programs.
 ///
-/// Panics if `examples` is empty or if the `examples` and `unpaired` data do not all have the same
-/// number of columns.
-pub fn learn<S0, S1, S2>(
+/// # Example: alternative interpretations
+///
+/// ```
+/// use synox::StringProgram;
+/// use synox::blinkfill;
+///
+/// let unpaired = &[vec!["Ben Bitdiddle"], vec!["Eva Lu Ator"]];
+/// let examples = &[(vec!["Alyssa P. Hacker"], "Hacker")];
+///
+/// let programs = blinkfill::learn_all(unpaired, examples).unwrap();
+/// let top = programs.top_k(3);
+/// assert_eq!(top.len(), 3);
+/// // the best program is the one returned by learn
+/// assert_eq!(top[0].0, blinkfill::learn(unpaired, examples).unwrap());
+/// // the alternatives all produce the example output, but they differ on other inputs
+/// for (prog, _score) in &top {
+///     assert_eq!(prog.run(&["Alyssa P. Hacker"]).as_deref(), Some("Hacker"));
+/// }
+/// ```
+pub fn learn_all<S0, S1, S2>(
     unpaired: &[Vec<S0>],
     examples: &[(Vec<S1>, S2)],
-) -> Option<impl StringProgram>
+) -> Result<ProgramSet, LearnError>
 where
     S0: AsRef<str>,
     S1: AsRef<str>,
     S2: AsRef<str>,
 {
-    // check length of all examples for consistency
-    if examples.is_empty() {
-        panic!("learn given no input-output examples");
+    Learner::new().learn_all(unpaired, examples)
+}
+
+/// A configurable learner.
+///
+/// The free functions [`learn`] and [`learn_all`] use a learner with the default options. A
+/// learner can be used to change these options, for example to pick a different [`Ranking`].
+///
+/// # Example: validating programs against the unpaired inputs
+///
+/// ```
+/// use synox::StringProgram;
+/// use synox::blinkfill::{Learner, Ranking};
+///
+/// let unpaired = &[vec!["Newark, United States"], vec!["Wellington, New Zealand"]];
+/// let examples = &[(vec!["Mumbai, India"], "India"),
+///                  (vec!["Los Angeles, United States of America"], "United States of America")];
+///
+/// let learner = Learner::new().ranking(Ranking::Validated);
+/// let prog = learner.learn(unpaired, examples).unwrap();
+/// for row in unpaired {
+///     assert!(prog.run(row).is_some());
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Learner {
+    ranking: Ranking,
+    tables: Vec<Table>,
+    // all of these are Token::Custom
+    custom_tokens: Vec<Token>,
+    // the maximum number of unpaired rows, and the seed for sampling them
+    max_unpaired: Option<(usize, u64)>,
+}
+
+impl Learner {
+    /// Creates a learner with the default options.
+    pub fn new() -> Self {
+        Self::default()
     }
-    let cols = examples[0].0.len();
-    for (i, (ex, _)) in examples.iter().enumerate() {
-        if ex.len() != cols {
-            panic!("incorrect column count in input-output example {}", i);
+
+    /// Sets the ranking used to choose among the programs consistent with the examples.
+    pub fn ranking(mut self, ranking: Ranking) -> Self {
+        self.ranking = ranking;
+        self
+    }
+
+    /// Adds a lookup table that learned programs can look up substrings of the input in.
+    ///
+    /// Whenever a part of an output is the value for some key in a table, and the key appears in the
+    /// input, the learner considers looking up that part of the input in the table. A table
+    /// replaces any table with the same name that was added before.
+    ///
+    /// # Example: month names
+    ///
+    /// ```
+    /// use synox::StringProgram;
+    /// use synox::blinkfill::{Learner, Table};
+    ///
+    /// let months = Table::new("months", vec![("1", "January"), ("2", "February"), ("3", "March"),
+    ///                                        ("4", "April"), ("5", "May"), ("6", "June")]);
+    /// let unpaired = &[vec!["2021-06-30"], vec!["2019-01-02"]];
+    /// let examples = &[(vec!["2020-3-14"], "March 2020"),
+    ///                  (vec!["2022-4-1"],  "April 2022")];
+    ///
+    /// let learner = Learner::new().table(months);
+    /// let prog = learner.learn(unpaired, examples).unwrap();
+    /// assert_eq!(prog.run(&["2023-5-17"]).unwrap(), "May 2023");
+    /// ```
+    pub fn table(mut self, table: Table) -> Self {
+        self.tables.retain(|t| t.name() != table.name());
+        self.tables.push(table);
+        self
+    }
+
+    /// Adds a custom token that positions in learned programs can be anchored to.
+    ///
+    /// Custom tokens are used like the built-in ones: the learner considers positions at the
+    /// start or end of every match of the token, and conditional programs can check how many
+    /// matches of it the input has. A token replaces any token with the same name that was added
+    /// before.
+    ///
+    /// # Example: product codes
+    ///
+    /// ```
+    /// use regex::Regex;
+    /// use synox::StringProgram;
+    /// use synox::blinkfill::{CustomToken, Learner};
+    ///
+    /// let sku = CustomToken::new("SKU", Regex::new(r"[A-Z]{2}-\d{4}-[A-Z]").unwrap(), 90).unwrap();
+    /// let unpaired = &[vec!["2 x ZZ-0001-Q (backorder) CH-4444-B"]];
+    /// let examples = &[(vec!["AB-1234-X shipped, replaces EF-9000-Z"], "AB-1234-X"),
+    ///                  (vec!["order 7: CD-5678-Y"], "CD-5678-Y")];
+    ///
+    /// let learner = Learner::new().token(sku);
+    /// let prog = learner.learn(unpaired, examples).unwrap();
+    /// assert_eq!(prog.to_string(), "Concat(SubStr(col0, Pos(SKU, 1, Start), Pos(SKU, 1, End)))");
+    /// assert_eq!(prog.run(&unpaired[0]).unwrap(), "ZZ-0001-Q");
+    /// ```
+    pub fn token(mut self, token: CustomToken) -> Self {
+        self.custom_tokens
+            .retain(|t| t.name() != Some(token.name()));
+        self.custom_tokens.push(Token::Custom(token));
+        self
+    }
+
+    /// Limits the number of unpaired rows that are learned from, sampling them with the given seed
+    /// when there are more.
+    ///
+    /// Rows are grouped by their format, the sequence of digits, letters, whitespace, and
+    /// punctuation in their columns. Every format gets at least one row in the sample, so rare
+    /// formats still inform the choice of program, and the rest of the sample is split among the
+    /// formats in proportion to their number of rows. The same rows and seed always give the same
+    /// sample. When there are more formats than the maximum, a random subset of the formats is
+    /// kept. [`Ranking::Validated`] only checks programs on the sampled rows, but
+    /// [`ProgramSet::suggest`] still considers all of the unpaired rows.
+    ///
+    /// # Example: many unpaired rows
+    ///
+    /// ```
+    /// use synox::StringProgram;
+    /// use synox::blinkfill::Learner;
+    ///
+    /// // thousands of phone numbers in one format, and a few in others
+    /// let mut unpaired: Vec<Vec<String>> = (0..5000)
+    ///     .map(|i| vec![format!("{}-{}-{}", 200 + i % 800, 100 + i % 900, 1000 + i % 9000)])
+    ///     .collect();
+    /// unpaired.push(vec![String::from("(204) 447-3924")]);
+    /// unpaired.push(vec![String::from("+1 212-456-7890")]);
+    /// let examples = &[(vec!["(123)-456-7890"],  "123"),
+    ///                  (vec!["+1 234-567-8901"], "234")];
+    ///
+    /// // learns from 100 of the unpaired rows, including the ones in rare formats
+    /// let learner = Learner::new().max_unpaired(100, 42);
+    /// let prog = learner.learn(&unpaired, examples).unwrap();
+    /// assert_eq!(prog.run(&["(617) 253-1337"]).unwrap(), "617");
+    /// ```
+    pub fn max_unpaired(mut self, max: usize, seed: u64) -> Self {
+        self.max_unpaired = Some((max, seed));
+        self
+    }
+
+    /// Learns a string program; see [`learn`].
+    pub fn learn<S0, S1, S2>(
+        &self,
+        unpaired: &[Vec<S0>],
+        examples: &[(Vec<S1>, S2)],
+    ) -> Result<Program, LearnError>
+    where
+        S0: AsRef<str>,
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        let (examples, unpaired, graph) =
+            prepare(unpaired, examples, &self.custom_tokens, self.max_unpaired)?;
+        let dag = Dag::learn(&examples, &graph, &self.tables);
+        if let Some(expr) = dag.top_ranked_expression(&graph, self.ranking) {
+            return Ok(Program::new(expr));
         }
+        // no single expression works for all of the examples, so try a conditional program
+        switch::learn(
+            &examples,
+            &unpaired,
+            self.ranking,
+            &self.tables,
+            &self.custom_tokens,
+        )
+        .ok_or(LearnError::NoProgram)
     }
-    for (i, ex) in unpaired.iter().enumerate() {
-        if ex.len() != cols {
-            panic!("incorrect column count in unpaired example {}", i);
+
+    /// Finds input-output examples that conflict with each other; see [`diagnose`].
+    ///
+    /// Like learning, this uses the learner's tables and custom tokens, so examples only conflict
+    /// if no program that this learner could learn is consistent with them. The unpaired inputs are
+    /// only checked for the right number of columns; they are not used to find the conflict.
+    pub fn diagnose<S0, S1, S2>(
+        &self,
+        unpaired: &[Vec<S0>],
+        examples: &[(Vec<S1>, S2)],
+    ) -> Result<Option<Conflict>, LearnError>
+    where
+        S0: AsRef<str>,
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        check_examples(unpaired, examples)?;
+        let examples: Vec<Example> = examples
+            .iter()
+            .map(|(row, output)| (row.iter().map(|x| x.as_ref()).collect(), output.as_ref()))
+            .collect();
+        // the unpaired inputs can only make the graph coarser, so examples that conflict on their
+        // own conflict with them too, and learning falls back to the examples alone
+        let graph = switch::graph(&examples, &[], &self.custom_tokens);
+        Ok(Dag::find_conflict(&examples, &graph, &self.tables))
+    }
+
+    /// Learns the set of all string programs consistent with the examples; see [`learn_all`].
+    pub fn learn_all<S0, S1, S2>(
+        &self,
+        unpaired: &[Vec<S0>],
+        examples: &[(Vec<S1>, S2)],
+    ) -> Result<ProgramSet, LearnError>
+    where
+        S0: AsRef<str>,
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        let (examples, _, graph) =
+            prepare(unpaired, examples, &self.custom_tokens, self.max_unpaired)?;
+        let learn = |graph: InputDataGraph| {
+            let dag = Dag::learn(&examples, &graph, &self.tables);
+            if dag.has_program() {
+                Some((graph, dag))
+            } else {
+                None
+            }
+        };
+        // like learning, fall back to the examples alone when the unpaired inputs make the graph
+        // too coarse for them
+        let (graph, dag) = learn(graph)
+            .or_else(|| learn(switch::graph(&examples, &[], &self.custom_tokens)))
+            .ok_or(LearnError::NoProgram)?;
+        let unpaired = unpaired
+            .iter()
+            .map(|row| row.iter().map(|x| String::from(x.as_ref())).collect())
+            .collect();
+        Ok(ProgramSet {
+            graph,
+            dag,
+            unpaired,
+            ranking: self.ranking,
+        })
+    }
+}
+
+/// How to choose among the programs consistent with the examples.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[non_exhaustive]
+pub enum Ranking {
+    /// Ranks programs as described in the BlinkFill paper.
+    ///
+    /// Positions are chosen based on how well they line up with the structure of all of the
+    /// inputs, but the chosen program might still fail on some of the unpaired inputs. This is the
+    /// default.
+    #[default]
+    Paper,
+    /// Like [`Ranking::Paper`], but only chooses positions that evaluate successfully on every
+    /// input, including the unpaired ones.
+    ///
+    /// When no choice of positions for a substring works on every input, this falls back to
+    /// choosing positions based on rank alone.
+    Validated,
+}
+
+/// The set of all string programs consistent with a set of examples.
+///
+/// This is returned by [`learn_all`].
+#[derive(Debug)]
+pub struct ProgramSet {
+    graph: InputDataGraph,
+    dag: Dag,
+    unpaired: Vec<Vec<String>>,
+    ranking: Ranking,
+}
+
+impl ProgramSet {
+    /// Returns the k highest-ranked distinct programs along with their scores.
+    ///
+    /// Programs are returned in order of decreasing score, so the first program is the one that
+    /// [`learn`] would return. Scores are only meaningful relative to other scores from the same
+    /// set. Fewer than k programs are returned if the set doesn't contain k distinct programs.
+    pub fn top_k(&self, k: usize) -> Vec<(Program, usize)> {
+        self.dag
+            .top_k_expressions(&self.graph, k, self.ranking)
+            .into_iter()
+            .map(|(score, expr)| (Program::new(expr), score))
+            .collect()
+    }
+
+    /// Returns the highest-ranked program together with its k - 1 best alternatives, which can be
+    /// used to estimate how confident the program is in its outputs.
+    ///
+    /// This returns `None` if k is zero.
+    pub fn ensemble(&self, k: usize) -> Option<Ensemble> {
+        let programs = self.top_k(k);
+        if programs.is_empty() {
+            None
+        } else {
+            Some(Ensemble { programs })
         }
     }
 
+    /// Suggests which unpaired row the user should provide an output for next.
+    ///
+    /// This compares the k highest-ranked programs on all of the unpaired rows, and returns the
+    /// row on which they disagree most, along with the outputs they produce on it. Adding an
+    /// example for this row and learning again rules out the programs that produce the wrong
+    /// output. Rows are compared by the number of programs that would be left in the worst case,
+    /// that is, the number of programs that agree with each other on the row's most common output;
+    /// the row with the fewest is chosen, and ties are broken by the number of distinct outputs
+    /// and then in favor of earlier rows. Programs that fail on a row are ignored for that row,
+    /// because they are ruled out by any example for it.
+    ///
+    /// This returns `None` if the top k programs agree on all of the unpaired rows.
+    ///
+    /// # Example: asking for the most useful example
+    ///
+    /// ```
+    /// use synox::blinkfill;
+    ///
+    /// let unpaired = &[vec!["Ben Bitdiddle"], vec!["Eva Lu Ator"], vec!["Louis Reasoner"]];
+    /// let examples = &[(vec!["Alyssa Hacker"], "Alyssa")];
+    ///
+    /// let programs = blinkfill::learn_all(unpaired, examples).unwrap();
+    /// let suggestion = programs.suggest(10).unwrap();
+    /// // the user is shown the competing outputs and asked to provide the right one
+    /// let row = &unpaired[suggestion.row()];
+    /// println!("What should the output be for {:?}? {:?}", row, suggestion.outputs());
+    /// assert!(suggestion.outputs().len() > 1);
+    /// ```
+    pub fn suggest(&self, k: usize) -> Option<Suggestion> {
+        let programs = self.top_k(k);
+        let mut best: Option<(usize, usize, Suggestion)> = None;
+        for (row, input) in self.unpaired.iter().enumerate() {
+            // group the programs by output, keeping the groups in order of their highest-ranked
+            // program
+            let mut groups: Vec<(String, usize)> = Vec::new();
+            for output in programs.iter().filter_map(|(prog, _)| prog.run(input)) {
+                match groups.iter_mut().find(|(o, _)| *o == output) {
+                    Some((_, count)) => *count += 1,
+                    None => groups.push((output, 1)),
+                }
+            }
+            if groups.len() < 2 {
+                continue;
+            }
+            let largest = groups.iter().map(|(_, count)| *count).max().unwrap();
+            let better = match &best {
+                None => true,
+                Some((best_largest, best_distinct, _)) => {
+                    (largest, Reverse(groups.len())) < (*best_largest, Reverse(*best_distinct))
+                }
+            };
+            if better {
+                let distinct = groups.len();
+                let outputs = groups.into_iter().map(|(o, _)| o).collect();
+                best = Some((largest, distinct, Suggestion { row, outputs }));
+            }
+        }
+        best.map(|(_, _, suggestion)| suggestion)
+    }
+}
+
+/// An unpaired row that would be informative to add as an example.
+///
+/// This is returned by [`ProgramSet::suggest`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Suggestion {
+    row: usize,
+    outputs: Vec<String>,
+}
+
+impl Suggestion {
+    /// Returns the index of the row in the list of unpaired rows.
+    pub fn row(&self) -> usize {
+        self.row
+    }
+
+    /// Returns the competing outputs for the row.
+    ///
+    /// Outputs are ordered by the rank of the highest-ranked program that produces them, so the
+    /// first output is the one produced by the best program that doesn't fail on the row.
+    pub fn outputs(&self) -> &[String] {
+        &self.outputs
+    }
+}
+
+// an input-output example, with the input columns borrowed from the caller
+type Example<'a> = (Vec<&'a str>, &'a str);
+
+// a row of input columns, borrowed from the caller
+type Row<'a> = Vec<&'a str>;
+
+// checks the examples, samples the unpaired rows if there is a maximum, and builds the input data
+// graph from all of the inputs; the sampled unpaired rows are returned along with the examples
+fn prepare<'a, S0, S1, S2>(
+    unpaired: &'a [Vec<S0>],
+    examples: &'a [(Vec<S1>, S2)],
+    custom_tokens: &[Token],
+    max_unpaired: Option<(usize, u64)>,
+) -> Result<(Vec<Example<'a>>, Vec<Row<'a>>, InputDataGraph), LearnError>
+where
+    S0: AsRef<str>,
+    S1: AsRef<str>,
+    S2: AsRef<str>,
+{
+    check_examples(unpaired, examples)?;
+
     // NOTE we construct all_unpaired here rather than having the caller pass it in, so we can
     // enforce the precondition of Dag::learn that the indices of the examples correspond to the
     // indices used in constructing the graph (so all unpaired has to be examples concatenated with
     // the unpaired, in that order)
-    let unpaired = unpaired
+    let mut unpaired: Vec<Row> = unpaired
         .iter()
-        .map(|row| row.iter().map(|x| x.as_ref()).collect());
-    let examples: Vec<(Vec<&str>, &str)> = examples
+        .map(|row| row.iter().map(|x| x.as_ref()).collect())
+        .collect();
+    if let Some((max, seed)) = max_unpaired {
+        let chosen = sample::sample(&unpaired, max, seed);
+        unpaired = chosen.into_iter().map(|i| unpaired[i].clone()).collect();
+    }
+    let examples: Vec<Example> = examples
         .iter()
         .map(|(row, output)| (row.iter().map(|x| x.as_ref()).collect(), output.as_ref()))
         .collect();
@@ -109,16 +579,250 @@ where
         .clone()
         .into_iter()
         .map(|(row, _)| row)
-        .chain(unpaired)
+        .chain(unpaired.iter().cloned())
         .collect();
-    let graph = InputDataGraph::new(&all_unpaired);
-    let dag = Dag::learn(&examples, &graph);
-    dag.top_ranked_expression(&graph)
+    let graph = InputDataGraph::new(&all_unpaired, custom_tokens);
+    Ok((examples, unpaired, graph))
+}
+
+// checks the preconditions of learning: there is at least one example, all rows have the same
+// number of columns, and no output is empty
+fn check_examples<S0, S1, S2>(
+    unpaired: &[Vec<S0>],
+    examples: &[(Vec<S1>, S2)],
+) -> Result<(), LearnError>
+where
+    S0: AsRef<str>,
+    S1: AsRef<str>,
+    S2: AsRef<str>,
+{
+    if examples.is_empty() {
+        return Err(LearnError::NoExamples);
+    }
+    let cols = examples[0].0.len();
+    let jagged = |kind, row, found| LearnError::JaggedRow {
+        kind,
+        row,
+        expected: cols,
+        found,
+    };
+    for (i, (ex, output)) in examples.iter().enumerate() {
+        if ex.len() != cols {
+            return Err(jagged(RowKind::Example, i, ex.len()));
+        }
+        if output.as_ref().is_empty() {
+            return Err(LearnError::EmptyOutput { row: i });
+        }
+    }
+    for (i, ex) in unpaired.iter().enumerate() {
+        if ex.len() != cols {
+            return Err(jagged(RowKind::Unpaired, i, ex.len()));
+        }
+    }
+    Ok(())
+}
+
+/// Finds input-output examples that conflict with each other.
+///
+/// When [`learn`] fails with [`LearnError::NoProgram`], or when it only finds a conditional
+/// program, this function can be used to find out which examples are to blame, for example to
+/// point out a mistyped example to the user. It returns `Ok(None)` if a single string expression is
+/// consistent with all of the examples.
+///
+/// The arguments are the same as for [`learn`]. This function is more expensive than learning,
+/// because it has to try many subsets of the examples. To diagnose examples for a learner with
+/// tables or custom tokens, use [`Learner::diagnose`]. The unpaired inputs are only checked for the
+/// right number of columns; they are not used to find the conflict.
+///
+/// # Example: finding a mistyped example
+///
+/// ```
+/// use synox::blinkfill;
+///
+/// let unpaired: &[Vec<&str>] = &[];
+/// let examples = &[(vec!["Alyssa P. Hacker"], "A. Hacker"),
+///                  (vec!["Ben Bitdiddle"],    "B. Bitdiddle"),
+///                  (vec!["Cy D. Fect"],       "C. Fcet"),
+///                  (vec!["Eva Lu Ator"],      "E. Ator")];
+///
+/// let conflict = blinkfill::diagnose(unpaired, examples).unwrap().unwrap();
+/// assert_eq!(conflict.culprits(), &[2]);
+/// ```
+pub fn diagnose<S0, S1, S2>(
+    unpaired: &[Vec<S0>],
+    examples: &[(Vec<S1>, S2)],
+) -> Result<Option<Conflict>, LearnError>
+where
+    S0: AsRef<str>,
+    S1: AsRef<str>,
+    S2: AsRef<str>,
+{
+    Learner::new().diagnose(unpaired, examples)
+}
+
+/// A set of input-output examples that are inconsistent with each other.
+///
+/// This is returned by [`diagnose`] and [`Learner::diagnose`]. Examples are identified by their index in the list of
+/// input-output examples.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Conflict {
+    examples: Vec<usize>,
+    culprits: Vec<usize>,
+}
+
+impl Conflict {
+    /// Returns a minimal set of examples that no single program is consistent with.
+    ///
+    /// The set is minimal in the sense that removing any example from it leaves a set of
+    /// consistent examples. When there are two examples that conflict with each other, a pair is
+    /// returned, which is the smallest possible conflict.
+    pub fn examples(&self) -> &[usize] {
+        &self.examples
+    }
+
+    /// Returns the examples whose removal alone makes all of the remaining examples consistent.
+    ///
+    /// This is empty if the conflict can't be resolved by removing a single example.
+    pub fn culprits(&self) -> &[usize] {
+        &self.culprits
+    }
+}
+
+/// A program together with its highest-ranked alternatives.
+///
+/// This is returned by [`ProgramSet::ensemble`]. Running an ensemble runs the best program, and
+/// the alternatives are used to estimate how confident the program is in its output: when all of
+/// the alternatives agree with the program on an input, the output is likely to be what the user
+/// intended, but when they disagree, the examples were ambiguous about what to do with that input.
+///
+/// # Example: sending low-confidence outputs for review
+///
+/// ```
+/// use synox::blinkfill;
+///
+/// let unpaired = &[vec!["Ben Bitdiddle"], vec!["Eva Lu Ator"], vec!["Louis Reasoner"]];
+/// let examples = &[(vec!["Alyssa Hacker"], "Alyssa")];
+///
+/// let ensemble = blinkfill::learn_all(unpaired, examples).unwrap().ensemble(10).unwrap();
+/// let (output, confidence) = ensemble.run_with_confidence(&["Eva Lu Ator"]).unwrap();
+/// if confidence < 0.9 {
+///     println!("please check: {}", output);
+/// }
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Ensemble {
+    programs: Vec<(Program, usize)>,
+}
+
+impl Ensemble {
+    /// Returns the highest-ranked program, which produces the outputs of the ensemble.
+    pub fn program(&self) -> &Program {
+        &self.programs[0].0
+    }
+
+    /// Runs the program on the given list of strings, returning the output along with a confidence
+    /// value between 0 and 1.
+    ///
+    /// The confidence is the fraction of the programs in the ensemble that produce the same output,
+    /// weighted by their scores; alternatives that fail on the input count as disagreeing. This
+    /// returns `None` if the program fails on the input.
+    pub fn run_with_confidence<S: AsRef<str>>(&self, row: &[S]) -> Option<(String, f64)> {
+        let output = self.program().run(row)?;
+        let mut agree = 0;
+        let mut total = 0;
+        for (prog, score) in &self.programs {
+            // every program has a positive score, because outputs are nonempty
+            total += score;
+            if prog.run(row).as_ref() == Some(&output) {
+                agree += score;
+            }
+        }
+        Some((output, agree as f64 / total as f64))
+    }
+}
+
+impl private::Sealed for Ensemble {}
+
+impl StringProgram for Ensemble {
+    fn run<S: AsRef<str>>(&self, row: &[S]) -> Option<String> {
+        self.program().run(row)
+    }
+}
+
+/// An error from learning a program.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub enum LearnError {
+    /// No input-output examples were given.
+    NoExamples,
+    /// A row has a different number of columns than the first input-output example.
+    JaggedRow {
+        /// Whether the row is an input-output example or an unpaired example.
+        kind: RowKind,
+        /// The index of the row among the rows of its kind.
+        row: usize,
+        /// The number of columns in the first input-output example.
+        expected: usize,
+        /// The number of columns in this row.
+        found: usize,
+    },
+    /// An input-output example has an empty output.
+    EmptyOutput {
+        /// The index of the input-output example.
+        row: usize,
+    },
+    /// No program is consistent with all of the input-output examples.
+    ///
+    /// [`diagnose`] can be used to find out which examples conflict with each other.
+    NoProgram,
 }
 
+/// The kind of a row given to [`learn`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RowKind {
+    /// An input-output example.
+    Example,
+    /// An unpaired example, where only the input is known.
+    Unpaired,
+}
+
+impl fmt::Display for LearnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LearnError::NoExamples => f.write_str("no input-output examples"),
+            LearnError::JaggedRow {
+                kind,
+                row,
+                expected,
+                found,
+            } => {
+                let kind = match kind {
+                    RowKind::Example => "input-output example",
+                    RowKind::Unpaired => "unpaired example",
+                };
+                write!(
+                    f,
+                    "{} {} has {} columns, expected {}",
+                    kind, row, found, expected
+                )
+            }
+            LearnError::EmptyOutput { row } => {
+                write!(f, "input-output example {} has an empty output", row)
+            }
+            LearnError::NoProgram => {
+                f.write_str("no program is consistent with all of the input-output examples")
+            }
+        }
+    }
+}
+
+impl Error for LearnError {}
+
 #[cfg(test)]
+#[allow(clippy::comparison_to_empty)]
 mod tests {
     use super::*;
+    use crate::StringProgram;
 
     #[test]
     fn end_to_end() {
@@ -219,4 +923,565 @@ mod tests {
             assert_eq!(prog.run(input).unwrap(), expected[i]);
         }
     }
+
+    #[test]
+    fn inspect_program() {
+        let unpaired = vec![
+            vec!["Newark, United States"],
+            vec!["Wellington, New Zealand"],
+        ];
+        let examples = vec![
+            (vec!["Mumbai, India"], "India"),
+            (
+                vec!["Los Angeles, United States of America"],
+                "United States of America",
+            ),
+        ];
+        let prog = learn(&unpaired, &examples).unwrap();
+        let parts = prog.expression().unwrap().parts();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].constant(), None);
+        assert_eq!(parts[0].column().unwrap().index(), 0);
+        let (p_start, p_end) = parts[0].positions().unwrap();
+        assert_eq!(p_start.token(), Some(&Token::AlphabetsWithSpaces));
+        assert_eq!(p_start.token().unwrap().literal(), None);
+        assert_eq!(p_start.occurrence().get(), -1);
+        assert_eq!(p_start.direction(), Some(Direction::Start));
+        assert_eq!(p_end.token(), Some(&Token::End));
+        assert_eq!(p_end.direction(), Some(Direction::Start));
+    }
+
+    #[test]
+    fn case_conversion() {
+        let unpaired = vec![vec!["ben bitdiddle"], vec!["eva lu ator"]];
+        let examples = vec![
+            (vec!["alyssa hacker"], "Alyssa Hacker"),
+            (vec!["louis reasoner"], "Louis Reasoner"),
+        ];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog.run(&unpaired[0]).unwrap(), "Ben Bitdiddle");
+        assert_eq!(prog.run(&unpaired[1]).unwrap(), "Eva Lu Ator");
+
+        let unpaired = vec![vec!["cd456"], vec!["x9"]];
+        let examples = vec![(vec!["ab123"], "AB123"), (vec!["efg7"], "EFG7")];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog.run(&unpaired[0]).unwrap(), "CD456");
+        assert_eq!(prog.run(&unpaired[1]).unwrap(), "X9");
+
+        // verbatim copies are preferred when they work
+        let unpaired = vec![vec!["Ben Bitdiddle"], vec!["Eva Lu Ator"]];
+        let examples = vec![
+            (vec!["Alyssa Hacker"], "Hacker"),
+            (vec!["Louis Reasoner"], "Reasoner"),
+        ];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert!(prog
+            .expression()
+            .unwrap()
+            .parts()
+            .iter()
+            .all(|e| e.conversion().is_none()));
+    }
+
+    #[test]
+    fn loops() {
+        // the initial of every word
+        let unpaired = vec![
+            vec!["Ben Bitdiddle"],
+            vec!["Cy D. Fect"],
+            vec!["Lem E. Tweakit"],
+        ];
+        let examples = vec![
+            (vec!["Alyssa P. Hacker"], "A.P.H."),
+            (vec!["Louis Reasoner"], "L.R."),
+        ];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog.run(&unpaired[0]).unwrap(), "B.B.");
+        assert_eq!(prog.run(&unpaired[1]).unwrap(), "C.D.F.");
+        assert_eq!(prog.run(&unpaired[2]).unwrap(), "L.E.T.");
+
+        // remove all the spaces
+        let unpaired = vec![vec!["(617) 253 1234"], vec!["1 2 3"], vec!["abc"]];
+        let examples = vec![
+            (vec!["+1 234 567 8901"], "+12345678901"),
+            (vec!["12 34"], "1234"),
+        ];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog.run(&unpaired[0]).unwrap(), "(617)2531234");
+        assert_eq!(prog.run(&unpaired[1]).unwrap(), "123");
+        assert_eq!(prog.run(&unpaired[2]).unwrap(), "abc");
+    }
+
+    #[test]
+    fn lookup_tables() {
+        let states = Table::new(
+            "states",
+            vec![
+                ("CA", "California"),
+                ("MA", "Massachusetts"),
+                ("NY", "New York"),
+            ],
+        );
+        let codes = Table::new("codes", vec![("X1", "widget"), ("X2", "gadget")]);
+        let unpaired = vec![vec!["Albany, NY", "X1"], vec!["Boston, MA", "X3"]];
+        let examples = vec![
+            (vec!["Cambridge, MA", "X2"], "gadget (Massachusetts)"),
+            (vec!["Palo Alto, CA", "X1"], "widget (California)"),
+        ];
+        let learner = Learner::new().table(states).table(codes);
+        let prog = learner.learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog.run(&unpaired[0]).unwrap(), "widget (New York)");
+        // keys that aren't in the table make the program fail
+        assert_eq!(prog.run(&unpaired[1]), None);
+        let tables: Vec<&str> = prog
+            .expression()
+            .unwrap()
+            .parts()
+            .iter()
+            .filter_map(|e| e.lookup())
+            .map(|(table, _)| table.name())
+            .collect();
+        assert_eq!(tables, vec!["codes", "states"]);
+        // without the tables, the examples can only be memorized
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_ne!(prog.run(&unpaired[0]).as_deref(), Some("widget (New York)"));
+    }
+
+    #[test]
+    fn number_formatting() {
+        // zero padding
+        let unpaired = vec![vec!["INV 42"], vec!["INV 1234"]];
+        let examples = vec![(vec!["INV 7"], "INV-007"), (vec!["INV 12"], "INV-012")];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog.run(&unpaired[0]).unwrap(), "INV-042");
+        assert_eq!(prog.run(&unpaired[1]).unwrap(), "INV-1234");
+
+        // rounding
+        let unpaired = vec![vec!["1.41421"], vec!["10"]];
+        let examples = vec![(vec!["3.14159"], "3.14"), (vec!["2.71828"], "2.72")];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog.run(&unpaired[0]).unwrap(), "1.41");
+        assert_eq!(prog.run(&unpaired[1]).unwrap(), "10.00");
+
+        // thousands separators, along with the other parts of the output
+        let unpaired = vec![vec!["Total: 12.5 USD"], vec!["Total: 1999 USD"]];
+        let examples = vec![
+            (vec!["Total: 1234.5 USD"], "$1,234.50"),
+            (vec!["Total: 7 USD"], "$7.00"),
+        ];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog.run(&unpaired[0]).unwrap(), "$12.50");
+        assert_eq!(prog.run(&unpaired[1]).unwrap(), "$1,999.00");
+        let (format, _) = prog.expression().unwrap().parts()[1]
+            .number_format()
+            .unwrap();
+        assert_eq!(format.decimals(), Some(2));
+        assert_eq!(format.separator(), Some(','));
+    }
+
+    #[test]
+    fn arithmetic() {
+        // the next episode, from the same data as end_to_end
+        let unpaired = vec![
+            vec!["The Expanse S03E12 REPACK 720p HDTV x264-LucidTV"],
+            vec!["The Expanse S02E04 720p HDTV x264-SVA"],
+        ];
+        let examples = vec![
+            (
+                vec!["The Expanse S01E04 1080p HDTV x264-BRISK"],
+                "Next: Season 1, Episode 5",
+            ),
+            (
+                vec!["The Expanse S03E09 REPACK 720p HDTV x264-LucidTV"],
+                "Next: Season 3, Episode 10",
+            ),
+        ];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_eq!(
+            prog.run(&unpaired[0]).unwrap(),
+            "Next: Season 3, Episode 13"
+        );
+        assert_eq!(prog.run(&unpaired[1]).unwrap(), "Next: Season 2, Episode 5");
+
+        // the year after, with two digits
+        let unpaired = vec![vec!["2003"], vec!["1979"]];
+        let examples = vec![(vec!["1985"], "'86"), (vec!["1990"], "'91")];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog.run(&unpaired[0]).unwrap(), "'04");
+        assert_eq!(prog.run(&unpaired[1]).unwrap(), "'80");
+        let (e, _) = prog.expression().unwrap().parts()[1].integer().unwrap();
+        assert_eq!(e.sum().unwrap().1, 1);
+
+        // a single example doesn't show that the offset isn't a coincidence
+        let examples = vec![(vec!["S01E04"], "Episode 5")];
+        let prog = learn(&unpaired, &examples).unwrap();
+        let parts = prog.expression().unwrap().parts();
+        assert!(parts.iter().all(|part| part.integer().is_none()));
+    }
+
+    #[test]
+    fn boundaries() {
+        // the number right before the letters; no single token ends there in both examples, and it
+        // isn't at the same distance from either end
+        let unpaired = vec![vec!["ab 4cd 9 x"]];
+        let examples = vec![(vec!["x 12ab 3 z"], "12"), (vec!["5 678cd y zz"], "678")];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog.run(&unpaired[0]).unwrap(), "4");
+        let (_, p_r) = prog.expression().unwrap().parts()[0].positions().unwrap();
+        assert_eq!(p_r.boundary().unwrap().0, &Token::Digits);
+    }
+
+    #[test]
+    fn nested_substrings() {
+        // the last word between the parentheses, which isn't at the same token match in the input
+        // in both examples
+        let unpaired = vec![vec!["Dr. Li (St Mary Hospital) Room 7"]];
+        let examples = vec![
+            (
+                vec!["Dr. Alice Smith (Boston General Hospital) Room 5"],
+                "Hospital",
+            ),
+            (vec!["Bob Jones (Mercy Clinic) Wing B Floor 2"], "Clinic"),
+        ];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog.run(&unpaired[0]).unwrap(), "Hospital");
+        assert!(prog.expression().unwrap().parts()[0].source().is_some());
+    }
+
+    #[test]
+    fn unicode() {
+        // indices count characters, so constant positions mean the same thing in every row
+        let unpaired = vec![vec!["Łódź 90-001"], vec!["東京都 100-0001"]];
+        let examples = vec![(vec!["Zürich 8001"], "Zür"), (vec!["Genève 1201"], "Gen")];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog.run(&unpaired[0]).unwrap(), "Łód");
+        assert_eq!(prog.run(&unpaired[1]).unwrap(), "東京都");
+        // the output has multibyte characters that occur in the input more than once
+        let examples = vec![(vec!["ééé"], "é"), (vec!["aé"], "é")];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_eq!(
+            prog.to_string(),
+            "Concat(SubStr(col0, ConstPos(2), ConstPos(3)))"
+        );
+    }
+
+    #[test]
+    fn dates() {
+        let unpaired = vec![vec!["Sep 30, 2019"], vec!["Feb 1, 2024"]];
+        let examples = vec![(vec!["Jul 3, 2021"], "2021-07-03")];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog.run(&unpaired[0]).unwrap(), "2019-09-30");
+        assert_eq!(prog.run(&unpaired[1]).unwrap(), "2024-02-01");
+
+        // the example can't tell day-first from month-first, but the unpaired rows can
+        let examples = vec![(vec!["03/07/2021"], "03.07.2021")];
+        let unpaired = vec![vec!["25/12/2021"], vec!["04/01/2022"]];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog.run(&unpaired[0]).unwrap(), "25.12.2021");
+        let (input, output, _) = prog.expression().unwrap().parts()[0]
+            .date_formats()
+            .unwrap();
+        assert_eq!(input.to_string(), "%d/%m/%Y");
+        assert_eq!(output.to_string(), "%d.%m.%Y");
+        let unpaired = vec![vec!["12/25/2021"], vec!["01/04/2022"]];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog.run(&unpaired[0]).unwrap(), "12.25.2021");
+
+        // several input formats, turned into one canonical format
+        let unpaired = vec![vec!["25/12/2020"], vec!["Jan 2, 2022"], vec!["2019-11-30"]];
+        let examples = vec![
+            (vec!["03/07/2021"], "2021-07-03"),
+            (vec!["Aug 4, 2021"], "2021-08-04"),
+            (vec!["2021-09-05"], "2021-09-05"),
+        ];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog.run(&unpaired[0]).unwrap(), "2020-12-25");
+        assert_eq!(prog.run(&unpaired[1]).unwrap(), "2022-01-02");
+        assert_eq!(prog.run(&unpaired[2]).unwrap(), "2019-11-30");
+    }
+
+    #[test]
+    fn top_k() {
+        let unpaired = vec![
+            vec!["Newark, United States"],
+            vec!["Wellington, New Zealand"],
+        ];
+        let examples = vec![
+            (vec!["Mumbai, India"], "India"),
+            (vec!["Paris, France"], "France"),
+        ];
+        let programs = learn_all(&unpaired, &examples).unwrap();
+        let top = programs.top_k(5);
+        assert_eq!(top.len(), 5);
+        assert_eq!(top[0].0, learn(&unpaired, &examples).unwrap());
+        for (i, (prog, score)) in top.iter().enumerate() {
+            for (input, output) in &examples {
+                assert_eq!(prog.run(input).as_deref(), Some(*output));
+            }
+            for (other, other_score) in &top[i + 1..] {
+                assert!(score >= other_score);
+                assert_ne!(prog, other);
+            }
+        }
+        assert!(programs.top_k(0).is_empty());
+        assert_eq!(
+            learn_all(&unpaired, &[(vec!["a"], "x"), (vec!["a"], "y")]).unwrap_err(),
+            LearnError::NoProgram
+        );
+    }
+
+    #[test]
+    fn suggest() {
+        let unpaired = vec![
+            vec!["Ben Bitdiddle"],
+            vec!["Cy D. Fect"],
+            vec!["Eva Lu Ator"],
+            vec!["Lem E. Tweakit"],
+        ];
+        let examples = vec![
+            (vec!["Alyssa P. Hacker"], "Hacker"),
+            (vec!["Louis Reasoner"], "Reasoner"),
+        ];
+        // all of the programs agree
+        let programs = learn_all(&unpaired, &examples).unwrap();
+        assert_eq!(programs.suggest(10), None);
+
+        let examples = vec![(vec!["Alyssa Hacker"], "Alyssa")];
+        let programs = learn_all(&unpaired, &examples).unwrap();
+        let suggestion = programs.suggest(10).unwrap();
+        let row = &unpaired[suggestion.row()];
+        let top = programs.top_k(10);
+        assert_eq!(suggestion.outputs()[0], top[0].0.run(row).unwrap());
+        let outputs: Vec<_> = top.iter().filter_map(|(prog, _)| prog.run(row)).collect();
+        for output in suggestion.outputs() {
+            assert!(outputs.contains(output));
+        }
+        // labeling the suggested row rules out the programs that disagree with the label
+        let label = row[0].split(' ').next().unwrap();
+        let mut examples = examples;
+        examples.push((row.clone(), label));
+        let programs = learn_all(&unpaired, &examples).unwrap();
+        for (prog, _) in programs.top_k(10) {
+            assert_eq!(prog.run(row).as_deref(), Some(label));
+        }
+    }
+
+    #[test]
+    fn confidence() {
+        let unpaired = vec![
+            vec!["Ben Bitdiddle"],
+            vec!["Eva Lu Ator"],
+            vec!["Louis Reasoner"],
+        ];
+        // the second example rules out the programs that use constants
+        let examples = vec![
+            (vec!["Alyssa Hacker"], "Alyssa"),
+            (vec!["Lem Tweakit"], "Lem"),
+        ];
+        let programs = learn_all(&unpaired, &examples).unwrap();
+        assert!(programs.ensemble(0).is_none());
+        let ensemble = programs.ensemble(10).unwrap();
+        assert_eq!(ensemble.program(), &learn(&unpaired, &examples).unwrap());
+        for row in &unpaired {
+            let (output, confidence) = ensemble.run_with_confidence(row).unwrap();
+            assert_eq!(Some(output), ensemble.run(row));
+            assert_eq!(confidence, 1.0);
+        }
+        assert_eq!(ensemble.run_with_confidence(&["Bob"]), None);
+
+        let examples = vec![(vec!["Alyssa Hacker"], "Alyssa")];
+        let programs = learn_all(&unpaired, &examples).unwrap();
+        let ensemble = programs.ensemble(10).unwrap();
+        for row in &unpaired {
+            let (output, confidence) = ensemble.run_with_confidence(row).unwrap();
+            assert_eq!(Some(output), ensemble.program().run(row));
+            assert!(0.0 < confidence && confidence < 1.0);
+        }
+        // a single program always agrees with itself
+        let ensemble = programs.ensemble(1).unwrap();
+        assert_eq!(ensemble.run_with_confidence(&unpaired[0]).unwrap().1, 1.0);
+    }
+
+    #[test]
+    fn max_unpaired() {
+        let unpaired = vec![
+            vec!["Ben Bitdiddle"],
+            vec!["Eva Lu Ator"],
+            vec!["Louis Reasoner"],
+        ];
+        let examples = vec![(vec!["Alyssa Hacker"], "Alyssa")];
+        // a maximum that all of the rows fit in doesn't change anything
+        let learner = Learner::new().max_unpaired(3, 0);
+        let prog = learner.learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog, learn(&unpaired, &examples).unwrap());
+        // the sample stays within the maximum, and the same seed gives the same sample and the
+        // same programs
+        let (_, sampled, _) = prepare(&unpaired, &examples, &[], Some((1, 7))).unwrap();
+        assert_eq!(sampled.len(), 1);
+        let (_, again, _) = prepare(&unpaired, &examples, &[], Some((1, 7))).unwrap();
+        assert_eq!(sampled, again);
+        assert!(unpaired.contains(&sampled[0]));
+        let learner = Learner::new().max_unpaired(1, 7);
+        let programs = learner.learn_all(&unpaired, &examples).unwrap();
+        let again = learner.learn_all(&unpaired, &examples).unwrap();
+        assert_eq!(programs.top_k(10), again.top_k(10));
+        // suggestions refer to all of the unpaired rows by their index in the caller's list, not
+        // just the sampled ones
+        let suggestion = programs.suggest(10).unwrap();
+        assert_eq!(again.suggest(10).as_ref(), Some(&suggestion));
+        let row = &unpaired[suggestion.row()];
+        let top = programs.top_k(10);
+        let outputs: Vec<_> = top.iter().filter_map(|(prog, _)| prog.run(row)).collect();
+        assert_eq!(suggestion.outputs()[0], outputs[0]);
+        for output in suggestion.outputs() {
+            assert!(outputs.contains(output));
+        }
+    }
+
+    #[test]
+    fn validated_ranking() {
+        let learner = Learner::new().ranking(Ranking::Validated);
+        // the highest-ranked positions are at a boundary between capitals and lowercase letters,
+        // which the unpaired rows don't have, but the first word can be extracted from every row
+        let unpaired = vec![vec!["7 Qq9 EFG h i"], vec!["3.5 -"]];
+        let examples = vec![(vec!["Qq9 12"], "Qq9")];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog.run(&unpaired[0]), None);
+        let prog = learner.learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog.run(&unpaired[0]).unwrap(), "7");
+        assert_eq!(prog.run(&unpaired[1]).unwrap(), "3");
+        let programs = learner.learn_all(&unpaired, &examples).unwrap();
+        assert_eq!(programs.top_k(1)[0].0, prog);
+        // the substring from the 3rd to the 6th character doesn't exist in the first row, and a
+        // constant isn't chosen instead of a substring just because the substring fails
+        let unpaired = vec![vec!["ab"], vec!["xyz-1"]];
+        let examples = vec![(vec!["abcdef-12"], "cdef")];
+        let prog = learner.learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog.run(&unpaired[0]), None);
+        assert_ne!(prog.run(&unpaired[1]).as_deref(), Some("cdef"));
+        // there is no way to get two digits from a single digit, so this falls back to rank
+        let unpaired = vec![vec!["12"], vec!["7"]];
+        let examples = vec![(vec!["2020"], "20"), (vec!["1999"], "19")];
+        let prog = learner.learn(&unpaired, &examples).unwrap();
+        assert_eq!(prog, learn(&unpaired, &examples).unwrap());
+        assert_eq!(prog.run(&unpaired[1]), None);
+    }
+
+    #[test]
+    fn errors() {
+        let none: &[Vec<&str>] = &[];
+        let no_examples: &[(Vec<&str>, &str)] = &[];
+        assert_eq!(learn(none, no_examples), Err(LearnError::NoExamples));
+        assert_eq!(
+            learn(none, &[(vec!["a", "b"], "a"), (vec!["c"], "c")]),
+            Err(LearnError::JaggedRow {
+                kind: RowKind::Example,
+                row: 1,
+                expected: 2,
+                found: 1
+            })
+        );
+        assert_eq!(
+            learn(&[vec!["a"], vec!["b", "c"]], &[(vec!["d"], "d")]),
+            Err(LearnError::JaggedRow {
+                kind: RowKind::Unpaired,
+                row: 1,
+                expected: 1,
+                found: 2
+            })
+        );
+        assert_eq!(
+            learn(none, &[(vec!["a"], "a"), (vec!["b"], "")]),
+            Err(LearnError::EmptyOutput { row: 1 })
+        );
+        assert_eq!(
+            learn(none, &[(vec!["a"], "x"), (vec!["a"], "y")]),
+            Err(LearnError::NoProgram)
+        );
+    }
+
+    #[test]
+    fn mixed_formats() {
+        let unpaired = vec![
+            vec!["Ator, Eva Lu"],
+            vec!["Cy D. Fect"],
+            vec!["Tweakit, Lem E."],
+        ];
+        let examples = vec![
+            (vec!["Hacker, Alyssa P."], "Alyssa Hacker"),
+            (vec!["Ben Bitdiddle"], "Ben Bitdiddle"),
+            (vec!["Reasoner, Louis"], "Louis Reasoner"),
+        ];
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert!(prog.expression().is_none());
+        assert_eq!(prog.run(&["Tweakit, Lem E."]).unwrap(), "Lem Tweakit");
+        assert_eq!(prog.run(&["Louis Reasoner"]).unwrap(), "Louis Reasoner");
+        // the conditional program round-trips through its DSL form
+        assert_eq!(prog.to_string().parse::<Program>().unwrap(), prog);
+        // learn_all only considers single expressions
+        assert_eq!(
+            learn_all(&unpaired, &examples).err(),
+            Some(LearnError::NoProgram)
+        );
+    }
+
+    #[test]
+    fn diagnose_typo() {
+        let unpaired = vec![vec!["Jay Peak_January_2019-GOPR0328.MP4"]];
+        let examples = vec![
+            (
+                vec!["Barcelona_January_2016-DSC02368.JPG"],
+                "2016/Barcelona",
+            ),
+            (vec!["Prague_August_2021-IMG_2618.JPG"], "2021/Prague"),
+            (
+                vec!["Smuggler's Notch_February_2017-GOPR0238.mp4"],
+                "2017/Smugglers Notch",
+            ),
+            (
+                vec!["Inyo National Forest_August_2019-DSC3583.jpg"],
+                "2019/Inyo National Forest",
+            ),
+        ];
+        assert_eq!(learn(&unpaired, &examples), Err(LearnError::NoProgram));
+        let conflict = diagnose(&unpaired, &examples).unwrap().unwrap();
+        assert_eq!(conflict.examples().len(), 2);
+        assert!(conflict.examples().contains(&2));
+        assert_eq!(conflict.culprits(), &[2]);
+        // fixing the typo resolves the conflict
+        let mut examples = examples;
+        examples[2].1 = "2017/Smuggler's Notch";
+        assert_eq!(diagnose(&unpaired, &examples), Ok(None));
+    }
+
+    #[test]
+    fn diagnose_with_learner() {
+        let codes = Table::new("codes", vec![("X1", "widget"), ("X2", "gadget")]);
+        let unpaired: Vec<Vec<&str>> = vec![];
+        let examples = vec![(vec!["order X1"], "widget"), (vec!["order X2"], "gadget")];
+        // the examples only conflict without the table
+        assert!(diagnose(&unpaired, &examples).unwrap().is_some());
+        let learner = Learner::new().table(codes);
+        assert_eq!(learner.diagnose(&unpaired, &examples), Ok(None));
+    }
+
+    #[test]
+    fn diagnose_agrees_with_learning() {
+        // the only expressions in common are loops over a single match, which ranking only falls
+        // back to when nothing else works, as the substring of the match
+        let unpaired: Vec<Vec<&str>> = vec![];
+        let examples = vec![(vec!["--"], "-"), (vec!["x21"], "1")];
+        assert_eq!(diagnose(&unpaired, &examples), Ok(None));
+        let programs = learn_all(&unpaired, &examples).unwrap();
+        assert!(!programs.top_k(1).is_empty());
+        let prog = learn(&unpaired, &examples).unwrap();
+        assert_eq!(
+            prog.expression().unwrap().to_string(),
+            "Concat(SubStr(SubStr(col0, Pos(NotDot, 1, Start), Pos(NotDot, 1, End)), ConstPos(-1), \
+             Pos(EndT, 1, Start)))"
+        );
+        assert_eq!(prog.run(&["--"]).unwrap(), "-");
+        assert_eq!(prog.run(&["x21"]).unwrap(), "1");
+        assert_eq!(prog.run(&["y35"]).unwrap(), "5");
+    }
 }
@@ -0,0 +1,262 @@
+use super::language::write_quoted;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// The thousands separators that learning considers.
+pub(crate) const SEPARATORS: &[char] = &[',', ' ', '\''];
+
+/// The largest width and number of decimals of a format, which bounds the length of its output.
+pub(crate) const MAX_DIGITS: usize = 64;
+
+/// A way of formatting a decimal number, such as "7" as "007", "3.14159" as "3.14", or "1234567"
+/// as "1,234,567".
+///
+/// Formatting first normalizes the number by removing leading zeros from its integer part. The
+/// integer part is then padded with zeros to the width, the fractional part is rounded to the
+/// number of decimals (with ties rounded away from zero), and the digits of the integer part are
+/// grouped by thousands with the separator.
+#[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct NumberFormat {
+    pub(crate) width: usize,
+    pub(crate) decimals: Option<usize>,
+    pub(crate) separator: Option<char>,
+}
+
+impl NumberFormat {
+    /// Returns the minimum number of digits in the integer part.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the number of digits after the decimal point, or `None` if the fractional part is
+    /// kept as it is.
+    pub fn decimals(&self) -> Option<usize> {
+        self.decimals
+    }
+
+    /// Returns the thousands separator, if any.
+    pub fn separator(&self) -> Option<char> {
+        self.separator
+    }
+
+    /// Formats a number, or returns `None` if the string isn't a decimal number, such as "1,234" or
+    /// "12.".
+    pub(crate) fn apply(&self, s: &str) -> Option<String> {
+        let (negative, int, frac) = parse_number(s)?;
+        let mut int: Vec<u8> = int.trim_start_matches('0').bytes().collect();
+        let mut frac: Vec<u8> = frac.bytes().collect();
+        if let Some(decimals) = self.decimals {
+            let round_up = frac.get(decimals).is_some_and(|d| *d >= b'5');
+            frac.resize(decimals, b'0');
+            if round_up && increment(&mut frac) {
+                // the carry goes into the integer part
+                if increment(&mut int) {
+                    int.insert(0, b'1');
+                }
+            }
+        }
+        // the digits are ASCII
+        let int = format!(
+            "{:0>1$}",
+            std::str::from_utf8(&int).unwrap(),
+            self.width.max(1)
+        );
+
+        let mut result = String::with_capacity(int.len() + frac.len() + 2);
+        if negative {
+            result.push('-');
+        }
+        for (i, d) in int.chars().enumerate() {
+            if i > 0 && (int.len() - i) % 3 == 0 {
+                if let Some(sep) = self.separator {
+                    result.push(sep);
+                }
+            }
+            result.push(d);
+        }
+        if !frac.is_empty() {
+            result.push('.');
+            result.extend(frac.iter().map(|d| *d as char));
+        }
+        Some(result)
+    }
+
+    // finds all of the formats that turn the number n into the string s
+    pub(crate) fn formats_for(n: &str, s: &str) -> Vec<Self> {
+        let (int, frac) = match s.find('.') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => (s, ""),
+        };
+        let digits = int.bytes().filter(u8::is_ascii_digit).count();
+        // a format can't be longer than MAX_DIGITS, but longer parts can still be kept as they are
+        let digits = digits.min(MAX_DIGITS);
+        let mut decimals = vec![None];
+        if frac.len() <= MAX_DIGITS {
+            decimals.push(Some(frac.len()));
+        }
+        let separators = Some(None)
+            .into_iter()
+            .chain(SEPARATORS.iter().map(|c| Some(*c)));
+        let mut formats = Vec::new();
+        for separator in separators {
+            for decimals in &decimals {
+                for width in 1..digits + 1 {
+                    let format = NumberFormat {
+                        width,
+                        decimals: *decimals,
+                        separator,
+                    };
+                    if format.apply(n).as_deref() == Some(s) {
+                        formats.push(format);
+                    }
+                }
+            }
+        }
+        formats
+    }
+}
+
+impl fmt::Display for NumberFormat {
+    // the arguments of a number formatting expression, after the expression being formatted
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, ", self.width)?;
+        match self.decimals {
+            Some(decimals) => write!(f, "{}, ", decimals)?,
+            None => f.write_str("Keep, ")?,
+        }
+        let separator = self.separator.map(String::from).unwrap_or_default();
+        write_quoted(f, &separator)
+    }
+}
+
+lazy_static! {
+    static ref NUMBER: Regex = Regex::new(r"^(-?)([0-9]+)(?:\.([0-9]+))?$").unwrap();
+    static ref NUMBERS: Regex = Regex::new(r"[0-9]+(?:\.[0-9]+)?").unwrap();
+    static ref INTEGERS: Regex = Regex::new(r"[0-9]+").unwrap();
+}
+
+// splits a decimal number into its sign, integer part, and fractional part
+fn parse_number(s: &str) -> Option<(bool, &str, &str)> {
+    let caps = NUMBER.captures(s)?;
+    let frac = caps.get(3).map_or("", |m| m.as_str());
+    Some((!caps[1].is_empty(), caps.get(2).unwrap().as_str(), frac))
+}
+
+// finds the byte ranges of all of the decimal numbers in a string; signs aren't included, because
+// a dash before a number is more often punctuation, and a sign can be copied separately
+pub(crate) fn find_numbers(s: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    NUMBERS.find_iter(s).map(|m| (m.start(), m.end()))
+}
+
+// finds the byte ranges of all of the runs of digits in a string, which are the integers that
+// arithmetic can start from
+pub(crate) fn find_integers(s: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    INTEGERS.find_iter(s).map(|m| (m.start(), m.end()))
+}
+
+// parses an integer, which is a string of digits with an optional minus sign
+pub(crate) fn parse_integer(s: &str) -> Option<i64> {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+// adds one to a string of digits, returning whether there is a carry out of it
+fn increment(digits: &mut [u8]) -> bool {
+    for d in digits.iter_mut().rev() {
+        if *d == b'9' {
+            *d = b'0';
+        } else {
+            *d += 1;
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(width: usize, decimals: Option<usize>, separator: Option<char>) -> NumberFormat {
+        NumberFormat {
+            width,
+            decimals,
+            separator,
+        }
+    }
+
+    #[test]
+    fn apply() {
+        assert_eq!(format(3, None, None).apply("7").unwrap(), "007");
+        assert_eq!(format(1, None, None).apply("007").unwrap(), "7");
+        assert_eq!(format(1, None, None).apply("000").unwrap(), "0");
+        assert_eq!(format(1, Some(2), None).apply("3.14159").unwrap(), "3.14");
+        assert_eq!(format(1, Some(2), None).apply("2.675").unwrap(), "2.68");
+        assert_eq!(format(1, Some(1), None).apply("-9.96").unwrap(), "-10.0");
+        assert_eq!(format(1, Some(0), None).apply("99.5").unwrap(), "100");
+        assert_eq!(format(1, Some(3), None).apply("1.5").unwrap(), "1.500");
+        assert_eq!(
+            format(1, None, Some(',')).apply("1234567").unwrap(),
+            "1,234,567"
+        );
+        assert_eq!(
+            format(8, Some(2), Some(' ')).apply("1234.5").unwrap(),
+            "00 001 234.50"
+        );
+        assert_eq!(format(1, None, Some(',')).apply("123").unwrap(), "123");
+        assert_eq!(format(1, None, None).apply("1,234"), None);
+        assert_eq!(format(1, None, None).apply("12."), None);
+        assert_eq!(format(1, None, None).apply(""), None);
+    }
+
+    #[test]
+    fn formats_for() {
+        assert_eq!(
+            NumberFormat::formats_for("7", "007"),
+            vec![format(3, None, None), format(3, Some(0), None)]
+                .into_iter()
+                .chain(SEPARATORS.iter().flat_map(|c| {
+                    vec![format(3, None, Some(*c)), format(3, Some(0), Some(*c))]
+                }))
+                .collect::<Vec<_>>()
+        );
+        let formats = NumberFormat::formats_for("3.14159", "3.14");
+        assert_eq!(formats[0], format(1, Some(2), None));
+        let formats = NumberFormat::formats_for("1234567", "1,234,567");
+        assert_eq!(formats.len(), 14);
+        assert_eq!(formats[0], format(1, None, Some(',')));
+        assert!(formats.iter().all(|f| f.separator == Some(',')));
+        assert!(NumberFormat::formats_for("1234567", "1,234").is_empty());
+        // formats are bounded even when the numbers aren't
+        let long = format!("{0}.{0}", "1".repeat(100));
+        let formats = NumberFormat::formats_for(&long, &long);
+        assert!(!formats.is_empty());
+        assert!(formats
+            .iter()
+            .all(|f| f.width <= MAX_DIGITS && f.decimals.is_none()));
+    }
+
+    #[test]
+    fn parse_integer() {
+        assert_eq!(super::parse_integer("0042"), Some(42));
+        assert_eq!(super::parse_integer("-7"), Some(-7));
+        assert_eq!(super::parse_integer("+7"), None);
+        assert_eq!(super::parse_integer("-"), None);
+        assert_eq!(super::parse_integer("1.5"), None);
+        assert_eq!(super::parse_integer("99999999999999999999"), None);
+    }
+
+    #[test]
+    fn find_numbers() {
+        let numbers: Vec<_> = super::find_numbers("INV-0042: $1,234.50").collect();
+        assert_eq!(numbers, vec![(4, 8), (11, 12), (13, 19)]);
+    }
+}
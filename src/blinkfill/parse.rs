@@ -0,0 +1,1091 @@
+//! Parser for the textual program DSL.
+//!
+//! This parses the format produced by the `Display` implementations in the [language] module, so
+//! that printing a program and parsing it back gives the same program. Whitespace (including
+//! newlines) is allowed between any two tokens. Substring and integer expressions can be nested at
+//! most 32 deep, which is far more than learned programs need.
+//!
+//! [language]: super::language
+
+use super::date::DateFormat;
+use super::language::{
+    Case, ColumnIndex, Condition, Direction, IntegerExpression, Occurrence, Position, Predicate,
+    Program, StringExpression, SubstringExpression,
+};
+use super::number::{NumberFormat, MAX_DIGITS};
+use super::table::Table;
+use super::token::{CustomToken, Token};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// An error from parsing a program.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ParseError {
+    line: usize,
+    column: usize,
+    message: String,
+}
+
+impl ParseError {
+    /// Returns the one-based line number where the error occurred.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Returns the one-based column number (in characters) where the error occurred.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// Returns a description of the error, without the location.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl Error for ParseError {}
+
+impl FromStr for StringExpression {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parser = Parser::new(s);
+        let expr = parser.string_expression()?;
+        parser.finish()?;
+        Ok(expr)
+    }
+}
+
+impl FromStr for Program {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Program::parse_with_tables(s, &[])
+    }
+}
+
+impl Program {
+    /// Parses a program that may look up strings in the given tables.
+    ///
+    /// The DSL only refers to tables by name, so programs that use tables can't be parsed with
+    /// [`str::parse`]; lookups in tables that aren't given are reported as errors.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use synox::StringProgram;
+    /// use synox::blinkfill::{Program, Table};
+    ///
+    /// let table = Table::new("states", vec![("CA", "California"), ("MA", "Massachusetts")]);
+    /// let prog = Program::parse_with_tables(
+    ///     "Concat(Lookup(\"states\", SubStr(col0, ConstPos(-2), Pos(EndT, 1, Start))))",
+    ///     &[table],
+    /// ).unwrap();
+    /// assert_eq!(prog.run(&["Cambridge, MA"]).unwrap(), "Massachusetts");
+    /// ```
+    pub fn parse_with_tables(s: &str, tables: &[Table]) -> Result<Self> {
+        Program::parse_with(s, tables, &[])
+    }
+
+    /// Parses a program that may look up strings in the given tables and use the given custom
+    /// tokens.
+    ///
+    /// Like tables, custom tokens are only referred to by name in the DSL. A name is looked up
+    /// among the built-in tokens first, so a custom token can't shadow a built-in one.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use regex::Regex;
+    /// use synox::StringProgram;
+    /// use synox::blinkfill::{CustomToken, Program};
+    ///
+    /// let sku = CustomToken::new("SKU", Regex::new(r"[A-Z]{2}-\d{4}").unwrap(), 90).unwrap();
+    /// let prog = Program::parse_with(
+    ///     "Concat(SubStr(col0, Pos(SKU, 1, Start), Pos(SKU, 1, End)))",
+    ///     &[],
+    ///     &[sku],
+    /// ).unwrap();
+    /// assert_eq!(prog.run(&["order AB-1234"]).unwrap(), "AB-1234");
+    /// ```
+    pub fn parse_with(s: &str, tables: &[Table], tokens: &[CustomToken]) -> Result<Self> {
+        let mut parser = Parser::new(s);
+        parser.tables = tables;
+        parser.tokens = tokens;
+        let prog = parser.program()?;
+        parser.finish()?;
+        Ok(prog)
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    // byte offset of the next unconsumed character
+    offset: usize,
+    tables: &'a [Table],
+    tokens: &'a [CustomToken],
+    // number of substring and integer expressions that are being parsed
+    depth: usize,
+}
+
+// how deeply expressions can be nested, so that parsing nested input fails instead of overflowing
+// the stack; unoptimized builds use about 20 KiB of stack per level
+const MAX_DEPTH: usize = 32;
+
+type Result<T> = std::result::Result<T, ParseError>;
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            input,
+            offset: 0,
+            tables: &[],
+            tokens: &[],
+            depth: 0,
+        }
+    }
+
+    fn error_at<T>(&self, offset: usize, message: String) -> Result<T> {
+        let mut line = 1;
+        let mut column = 1;
+        for c in self.input[..offset].chars() {
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        Err(ParseError {
+            line,
+            column,
+            message,
+        })
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.offset..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.offset += rest.len() - rest.trim_start().len();
+    }
+
+    // describes the next token, for error messages
+    fn found(&self) -> String {
+        match self.peek() {
+            None => String::from("end of input"),
+            Some(c) if c.is_alphanumeric() || c == '_' => {
+                let word: String = self
+                    .rest()
+                    .chars()
+                    .take_while(|c| c.is_alphanumeric() || *c == '_')
+                    .collect();
+                format!("`{}`", word)
+            }
+            Some(c) => format!("`{}`", c),
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.offset += c.len_utf8();
+            Ok(())
+        } else {
+            self.error_at(
+                self.offset,
+                format!("expected `{}`, found {}", c, self.found()),
+            )
+        }
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.skip_whitespace();
+        if self.offset < self.input.len() {
+            return self.error_at(
+                self.offset,
+                format!("expected end of input, found {}", self.found()),
+            );
+        }
+        Ok(())
+    }
+
+    // returns the identifier and its starting offset
+    fn identifier(&mut self, what: &str) -> Result<(&'a str, usize)> {
+        self.skip_whitespace();
+        let start = self.offset;
+        let len = self
+            .rest()
+            .char_indices()
+            .find(|(i, c)| {
+                !(c.is_ascii_alphabetic() || *c == '_' || (*i > 0 && c.is_ascii_digit()))
+            })
+            .map(|(i, _)| i)
+            .unwrap_or_else(|| self.rest().len());
+        if len == 0 {
+            return self.error_at(start, format!("expected {}, found {}", what, self.found()));
+        }
+        self.offset += len;
+        Ok((&self.input[start..start + len], start))
+    }
+
+    fn integer<T: FromStr>(&mut self) -> Result<(T, usize)> {
+        self.skip_whitespace();
+        let start = self.offset;
+        let rest = self.rest();
+        let sign = if rest.starts_with('-') { 1 } else { 0 };
+        let digits = rest[sign..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len() - sign);
+        if digits == 0 {
+            return self.error_at(
+                start,
+                format!("expected an integer, found {}", self.found()),
+            );
+        }
+        let text = &rest[..sign + digits];
+        match text.parse() {
+            Ok(k) => {
+                self.offset += text.len();
+                Ok((k, start))
+            }
+            Err(_) => self.error_at(start, format!("integer `{}` is out of range", text)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.skip_whitespace();
+        let start = self.offset;
+        if self.peek() != Some('"') {
+            return self.error_at(start, format!("expected a string, found {}", self.found()));
+        }
+        self.offset += 1;
+        let mut s = String::new();
+        loop {
+            let c = match self.peek() {
+                None => return self.error_at(start, String::from("unterminated string")),
+                Some(c) => c,
+            };
+            let c_offset = self.offset;
+            self.offset += c.len_utf8();
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escape = self.peek();
+                    if let Some(e) = escape {
+                        self.offset += e.len_utf8();
+                    }
+                    let escaped = match escape {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => self.unicode_escape(c_offset)?,
+                        _ => {
+                            return self.error_at(c_offset, String::from("invalid escape sequence"))
+                        }
+                    };
+                    s.push(escaped);
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    // parses the `{...}` part of a `\u{...}` escape
+    fn unicode_escape(&mut self, escape_offset: usize) -> Result<char> {
+        let rest = self.rest();
+        let close = rest.find('}');
+        match (rest.starts_with('{'), close) {
+            (true, Some(close)) => {
+                let c = u32::from_str_radix(&rest[1..close], 16)
+                    .ok()
+                    .and_then(std::char::from_u32);
+                match c {
+                    Some(c) => {
+                        self.offset += close + 1;
+                        Ok(c)
+                    }
+                    None => self.error_at(escape_offset, String::from("invalid unicode escape")),
+                }
+            }
+            _ => self.error_at(escape_offset, String::from("invalid unicode escape")),
+        }
+    }
+
+    fn program(&mut self) -> Result<Program> {
+        self.skip_whitespace();
+        let start = self.offset;
+        let (name, _) = self.identifier("`Concat` or `Switch`")?;
+        if name != "Switch" {
+            // not a conditional program, so parse it again as a string expression
+            self.offset = start;
+            return self.string_expression().map(Program::new);
+        }
+        self.expect('(')?;
+        let mut branches = vec![self.branch()?];
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(',') {
+                break;
+            }
+            self.offset += 1;
+            branches.push(self.branch()?);
+        }
+        self.expect(')')?;
+        // there is always a first branch
+        Ok(Program::switch(branches).unwrap())
+    }
+
+    fn branch(&mut self) -> Result<(Predicate, StringExpression)> {
+        self.expect('(')?;
+        let pred = self.predicate()?;
+        self.expect(',')?;
+        let expr = self.string_expression()?;
+        self.expect(')')?;
+        Ok((pred, expr))
+    }
+
+    fn predicate(&mut self) -> Result<Predicate> {
+        self.skip_whitespace();
+        let start = self.offset;
+        let (name, offset) = self.identifier("a predicate")?;
+        match name {
+            "True" => Ok(Predicate(vec![])),
+            "Match" => {
+                self.offset = start;
+                Ok(Predicate(vec![self.condition()?]))
+            }
+            "And" => {
+                self.expect('(')?;
+                let mut conditions = vec![self.condition()?];
+                loop {
+                    self.skip_whitespace();
+                    if self.peek() != Some(',') {
+                        break;
+                    }
+                    self.offset += 1;
+                    conditions.push(self.condition()?);
+                }
+                self.expect(')')?;
+                Ok(Predicate(conditions))
+            }
+            _ => self.error_at(offset, format!("expected a predicate, found `{}`", name)),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition> {
+        let (name, offset) = self.identifier("`Match`")?;
+        if name != "Match" {
+            return self.error_at(offset, format!("expected `Match`, found `{}`", name));
+        }
+        self.expect('(')?;
+        let column = self.column_index()?;
+        self.expect(',')?;
+        let token = self.token()?;
+        self.expect(',')?;
+        let (count, offset) = self.integer::<isize>()?;
+        if count < 0 {
+            return self.error_at(offset, String::from("count must not be negative"));
+        }
+        self.expect(')')?;
+        Ok(Condition {
+            column,
+            token,
+            count: count as usize,
+        })
+    }
+
+    fn string_expression(&mut self) -> Result<StringExpression> {
+        let (name, offset) = self.identifier("`Concat`")?;
+        if name != "Concat" {
+            return self.error_at(offset, format!("expected `Concat`, found `{}`", name));
+        }
+        self.expect('(')?;
+        let mut parts = vec![];
+        self.skip_whitespace();
+        if self.peek() != Some(')') {
+            parts.push(self.substring_expression()?);
+            loop {
+                self.skip_whitespace();
+                if self.peek() != Some(',') {
+                    break;
+                }
+                self.offset += 1;
+                parts.push(self.substring_expression()?);
+            }
+        }
+        self.expect(')')?;
+        Ok(StringExpression(parts))
+    }
+
+    // parses an expression one level deeper than the current one
+    fn nested<T>(&mut self, parse: fn(&mut Self) -> Result<T>) -> Result<T> {
+        self.skip_whitespace();
+        if self.depth == MAX_DEPTH {
+            return self.error_at(
+                self.offset,
+                format!("expressions are nested more than {} deep", MAX_DEPTH),
+            );
+        }
+        self.depth += 1;
+        let e = parse(self);
+        self.depth -= 1;
+        e
+    }
+
+    fn substring_expression(&mut self) -> Result<SubstringExpression> {
+        self.nested(Self::substring_expression_inner)
+    }
+
+    fn substring_expression_inner(&mut self) -> Result<SubstringExpression> {
+        let (name, offset) = self.identifier("a substring expression")?;
+        match name {
+            "Const" => {
+                self.expect('(')?;
+                let s = self.string()?;
+                self.expect(')')?;
+                Ok(SubstringExpression::ConstantString(s))
+            }
+            "SubStr" => {
+                self.expect('(')?;
+                // the source is a column or another expression, and only columns start with col
+                self.skip_whitespace();
+                if !self.rest().starts_with("col") {
+                    let e = self.substring_expression()?;
+                    let (p_start, p_end) = self.substring_positions()?;
+                    return Ok(SubstringExpression::SubstringOf(
+                        Box::new(e),
+                        p_start,
+                        p_end,
+                    ));
+                }
+                let ci = self.column_index()?;
+                let (p_start, p_end) = self.substring_positions()?;
+                Ok(SubstringExpression::Substring(ci, p_start, p_end))
+            }
+            "Upper" | "Lower" | "Proper" => {
+                let case = match name {
+                    "Upper" => Case::Upper,
+                    "Lower" => Case::Lower,
+                    _ => Case::Proper,
+                };
+                self.expect('(')?;
+                let e = self.substring_expression()?;
+                self.expect(')')?;
+                Ok(SubstringExpression::Convert(case, Box::new(e)))
+            }
+            "Loop" => {
+                self.expect('(')?;
+                let ci = self.column_index()?;
+                self.expect(',')?;
+                let token = self.token()?;
+                self.expect(',')?;
+                let body = self.substring_expression()?;
+                self.expect(',')?;
+                let sep = self.string()?;
+                self.expect(')')?;
+                Ok(SubstringExpression::Loop(ci, token, Box::new(body), sep))
+            }
+            "Lookup" => {
+                self.expect('(')?;
+                let table = self.table()?;
+                self.expect(',')?;
+                let e = self.substring_expression()?;
+                self.expect(')')?;
+                Ok(SubstringExpression::Lookup(table, Box::new(e)))
+            }
+            "Number" => {
+                self.expect('(')?;
+                let e = self.substring_expression()?;
+                self.expect(',')?;
+                let format = self.number_format()?;
+                self.expect(')')?;
+                Ok(SubstringExpression::Number(format, Box::new(e)))
+            }
+            "Date" => {
+                self.expect('(')?;
+                let e = self.substring_expression()?;
+                self.expect(',')?;
+                let input = self.date_format()?;
+                self.expect(',')?;
+                let output = self.date_format()?;
+                self.expect(')')?;
+                Ok(SubstringExpression::Date(input, output, Box::new(e)))
+            }
+            "Integer" => {
+                self.expect('(')?;
+                let e = self.integer_expression()?;
+                self.expect(',')?;
+                let format = self.number_format()?;
+                self.expect(')')?;
+                Ok(SubstringExpression::Integer(e, format))
+            }
+            _ => self.error_at(
+                offset,
+                format!("expected a substring expression, found `{}`", name),
+            ),
+        }
+    }
+
+    // parses the rest of a substring expression after its source
+    fn substring_positions(&mut self) -> Result<(Position, Position)> {
+        self.expect(',')?;
+        let p_start = self.position()?;
+        self.expect(',')?;
+        let p_end = self.position()?;
+        self.expect(')')?;
+        Ok((p_start, p_end))
+    }
+
+    fn integer_expression(&mut self) -> Result<IntegerExpression> {
+        self.nested(Self::integer_expression_inner)
+    }
+
+    fn integer_expression_inner(&mut self) -> Result<IntegerExpression> {
+        let (name, offset) = self.identifier("an integer expression")?;
+        match name {
+            "Parse" => {
+                self.expect('(')?;
+                let e = self.substring_expression()?;
+                self.expect(')')?;
+                Ok(IntegerExpression::Parse(Box::new(e)))
+            }
+            "Add" => {
+                self.expect('(')?;
+                let e = self.integer_expression()?;
+                self.expect(',')?;
+                let (k, _) = self.integer()?;
+                self.expect(')')?;
+                Ok(IntegerExpression::Add(Box::new(e), k))
+            }
+            _ => self.error_at(
+                offset,
+                format!("expected an integer expression, found `{}`", name),
+            ),
+        }
+    }
+
+    fn column_index(&mut self) -> Result<ColumnIndex> {
+        let (name, offset) = self.identifier("a column")?;
+        let index = name.strip_prefix("col").and_then(|digits| {
+            // reject things like col+1 or col01, which don't round-trip
+            if digits.starts_with('0') && digits.len() > 1 {
+                None
+            } else {
+                digits.parse().ok()
+            }
+        });
+        match index {
+            Some(index) => Ok(ColumnIndex(index)),
+            None => self.error_at(offset, format!("expected a column, found `{}`", name)),
+        }
+    }
+
+    fn occurrence(&mut self) -> Result<Occurrence> {
+        let (k, offset) = self.integer()?;
+        if k == 0 {
+            return self.error_at(offset, String::from("occurrence must be nonzero"));
+        }
+        Ok(Occurrence(k))
+    }
+
+    fn position(&mut self) -> Result<Position> {
+        let (name, offset) = self.identifier("a position")?;
+        match name {
+            "Pos" => {
+                self.expect('(')?;
+                let token = self.token()?;
+                self.expect(',')?;
+                let k = self.occurrence()?;
+                self.expect(',')?;
+                let dir = self.direction()?;
+                self.expect(')')?;
+                Ok(Position::Match(token, k, dir))
+            }
+            "ConstPos" => {
+                self.expect('(')?;
+                let k = self.occurrence()?;
+                self.expect(')')?;
+                Ok(Position::ConstantPosition(k))
+            }
+            "BoundaryPos" => {
+                self.expect('(')?;
+                let before = self.token()?;
+                self.expect(',')?;
+                let after = self.token()?;
+                self.expect(',')?;
+                let k = self.occurrence()?;
+                self.expect(')')?;
+                Ok(Position::Boundary(before, after, k))
+            }
+            _ => self.error_at(offset, format!("expected a position, found `{}`", name)),
+        }
+    }
+
+    fn token(&mut self) -> Result<Token> {
+        self.skip_whitespace();
+        let offset = self.offset;
+        if self.peek() == Some('"') {
+            let s = self.string()?;
+            return match Token::literal_from(&s) {
+                Some(token) => Ok(token),
+                None => self.error_at(offset, String::from("literal token must be nonempty")),
+            };
+        }
+        let (name, offset) = self.identifier("a token")?;
+        if let Some(token) = Token::from_name(name) {
+            return Ok(token);
+        }
+        match self.tokens.iter().find(|t| t.name() == name) {
+            Some(token) => Ok(Token::Custom(token.clone())),
+            None => self.error_at(offset, format!("unknown token `{}`", name)),
+        }
+    }
+
+    fn table(&mut self) -> Result<Table> {
+        self.skip_whitespace();
+        let offset = self.offset;
+        let name = self.string()?;
+        match self.tables.iter().find(|t| t.name() == name) {
+            Some(table) => Ok(table.clone()),
+            None => self.error_at(offset, format!("unknown table `{}`", name)),
+        }
+    }
+
+    fn number_format(&mut self) -> Result<NumberFormat> {
+        let (width, offset) = self.integer::<isize>()?;
+        if width < 1 {
+            return self.error_at(offset, String::from("width must be positive"));
+        }
+        if width > MAX_DIGITS as isize {
+            return self.error_at(offset, format!("width must be at most {}", MAX_DIGITS));
+        }
+        self.expect(',')?;
+        self.skip_whitespace();
+        let decimals = if self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            let (name, offset) = self.identifier("`Keep`")?;
+            if name != "Keep" {
+                return self.error_at(offset, format!("expected `Keep`, found `{}`", name));
+            }
+            None
+        } else {
+            let (decimals, offset) = self.integer::<isize>()?;
+            if decimals < 0 {
+                return self.error_at(offset, String::from("decimals must not be negative"));
+            }
+            if decimals > MAX_DIGITS as isize {
+                return self.error_at(offset, format!("decimals must be at most {}", MAX_DIGITS));
+            }
+            Some(decimals as usize)
+        };
+        self.expect(',')?;
+        self.skip_whitespace();
+        let offset = self.offset;
+        let separator = self.string()?;
+        let mut chars = separator.chars();
+        let separator = match (chars.next(), chars.next()) {
+            (c, None) => c,
+            _ => {
+                return self.error_at(
+                    offset,
+                    String::from("separator must be empty or a single character"),
+                )
+            }
+        };
+        Ok(NumberFormat {
+            width: width as usize,
+            decimals,
+            separator,
+        })
+    }
+
+    fn date_format(&mut self) -> Result<DateFormat> {
+        self.skip_whitespace();
+        let offset = self.offset;
+        let pattern = self.string()?;
+        match DateFormat::from_pattern(&pattern) {
+            Ok(format) => Ok(format),
+            Err(message) => self.error_at(offset, message),
+        }
+    }
+
+    fn direction(&mut self) -> Result<Direction> {
+        let (name, offset) = self.identifier("`Start` or `End`")?;
+        match name {
+            "Start" => Ok(Direction::Start),
+            "End" => Ok(Direction::End),
+            _ => self.error_at(
+                offset,
+                format!("expected `Start` or `End`, found `{}`", name),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::token::NAMES;
+    use super::*;
+    use crate::StringProgram;
+    use proptest::prelude::*;
+
+    fn arb_occurrence() -> impl Strategy<Value = Occurrence> {
+        prop_oneof![-20isize..0, 1isize..20].prop_map(Occurrence)
+    }
+
+    fn arb_token() -> impl Strategy<Value = Token> {
+        prop_oneof![
+            (0..NAMES.len()).prop_map(|i| NAMES[i].0.clone()),
+            any::<String>()
+                .prop_filter("literal tokens are nonempty", |s| !s.is_empty())
+                .prop_map(Token::Literal),
+        ]
+    }
+
+    fn arb_position() -> impl Strategy<Value = Position> {
+        prop_oneof![
+            (arb_token(), arb_occurrence(), any::<bool>()).prop_map(|(token, k, start)| {
+                let dir = if start {
+                    Direction::Start
+                } else {
+                    Direction::End
+                };
+                Position::Match(token, k, dir)
+            }),
+            arb_occurrence().prop_map(Position::ConstantPosition),
+            (arb_token(), arb_token(), arb_occurrence())
+                .prop_map(|(before, after, k)| Position::Boundary(before, after, k)),
+        ]
+    }
+
+    const TABLES: &[&str] = &["months", "a \"quoted\" name"];
+
+    fn tables() -> Vec<Table> {
+        TABLES
+            .iter()
+            .map(|name| Table::new(*name, vec![("1", "January")]))
+            .collect()
+    }
+
+    fn arb_number_format() -> impl Strategy<Value = NumberFormat> {
+        (
+            1..20usize,
+            prop::option::of(0..10usize),
+            any::<Option<char>>(),
+        )
+            .prop_map(|(width, decimals, separator)| NumberFormat {
+                width,
+                decimals,
+                separator,
+            })
+    }
+
+    fn arb_date_format() -> impl Strategy<Value = DateFormat> {
+        let directives = [
+            "Y", "y", "m", "-m", "b", "B", "d", "-d", "a", "A", "H", "-H", "M", "S",
+        ];
+        let item = prop_oneof![
+            (0..directives.len()).prop_map(move |i| format!("%{}", directives[i])),
+            any::<String>().prop_map(|s| s.replace('%', "%%")),
+        ];
+        prop::collection::vec(item, 0..5)
+            .prop_map(|items| DateFormat::from_pattern(&items.concat()).unwrap())
+    }
+
+    fn arb_case() -> impl Strategy<Value = Case> {
+        prop_oneof![Just(Case::Upper), Just(Case::Lower), Just(Case::Proper)]
+    }
+
+    fn arb_substring_expression() -> impl Strategy<Value = SubstringExpression> {
+        let leaf = prop_oneof![
+            any::<String>().prop_map(SubstringExpression::ConstantString),
+            (0..10usize, arb_position(), arb_position()).prop_map(|(ci, p_l, p_r)| {
+                SubstringExpression::Substring(ColumnIndex(ci), p_l, p_r)
+            }),
+        ];
+        leaf.prop_recursive(2, 4, 1, |inner| {
+            prop_oneof![
+                (arb_case(), inner.clone())
+                    .prop_map(|(case, e)| SubstringExpression::Convert(case, Box::new(e))),
+                (arb_number_format(), inner.clone())
+                    .prop_map(|(format, e)| SubstringExpression::Number(format, Box::new(e))),
+                (
+                    inner.clone(),
+                    prop::collection::vec(-1000..1000i64, 0..3),
+                    arb_number_format()
+                )
+                    .prop_map(|(e, ks, format)| {
+                        let parsed = IntegerExpression::Parse(Box::new(e));
+                        let sum = ks
+                            .into_iter()
+                            .fold(parsed, |e, k| IntegerExpression::Add(Box::new(e), k));
+                        SubstringExpression::Integer(sum, format)
+                    }),
+                (arb_date_format(), arb_date_format(), inner.clone()).prop_map(
+                    |(input, output, e)| SubstringExpression::Date(input, output, Box::new(e))
+                ),
+                (inner.clone(), arb_position(), arb_position()).prop_map(|(e, p_l, p_r)| {
+                    SubstringExpression::SubstringOf(Box::new(e), p_l, p_r)
+                }),
+                (0..TABLES.len(), inner.clone()).prop_map(|(i, e)| {
+                    SubstringExpression::Lookup(tables()[i].clone(), Box::new(e))
+                }),
+                (0..10usize, arb_token(), inner, any::<String>()).prop_map(
+                    |(ci, token, body, sep)| {
+                        SubstringExpression::Loop(ColumnIndex(ci), token, Box::new(body), sep)
+                    }
+                ),
+            ]
+        })
+    }
+
+    fn arb_string_expression() -> impl Strategy<Value = StringExpression> {
+        prop::collection::vec(arb_substring_expression(), 0..5).prop_map(StringExpression)
+    }
+
+    fn arb_predicate() -> impl Strategy<Value = Predicate> {
+        let condition =
+            (0..10usize, arb_token(), 0..5usize).prop_map(|(ci, token, count)| Condition {
+                column: ColumnIndex(ci),
+                token,
+                count,
+            });
+        prop::collection::vec(condition, 0..3).prop_map(Predicate)
+    }
+
+    fn arb_program() -> impl Strategy<Value = Program> {
+        prop_oneof![
+            arb_string_expression().prop_map(Program::new),
+            prop::collection::vec((arb_predicate(), arb_string_expression()), 1..4)
+                .prop_map(|branches| Program::switch(branches).unwrap()),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(e in arb_string_expression()) {
+            let printed = e.to_string();
+            let tables = tables();
+            let mut parser = Parser::new(&printed);
+            parser.tables = &tables;
+            prop_assert_eq!(parser.string_expression(), Ok(e));
+            prop_assert_eq!(parser.finish(), Ok(()));
+        }
+
+        #[test]
+        fn round_trip_program(prog in arb_program()) {
+            let printed = prog.to_string();
+            prop_assert_eq!(Program::parse_with_tables(&printed, &tables()), Ok(prog));
+        }
+
+        // not about parsing, but this is where the programs are generated
+        #[test]
+        fn runs_without_panicking(
+            prog in arb_program(),
+            row in prop::collection::vec("[a-zA-Z0-9 ,.é東\u{1F600}]{0,12}", 1..3),
+        ) {
+            let _ = prog.run(&row);
+        }
+    }
+
+    #[test]
+    fn whitespace() {
+        let e: StringExpression = "
+            Concat(
+                SubStr(col1, Pos(\"(\", 1, End), Pos( CAPS , -1 , Start )),
+                Const(\"\\u{1F600}\")
+            )"
+        .parse()
+        .unwrap();
+        assert_eq!(
+            e.to_string(),
+            "Concat(SubStr(col1, Pos(\"(\", 1, End), Pos(CAPS, -1, Start)), Const(\"\u{1F600}\"))"
+        );
+    }
+
+    fn error(s: &str) -> (usize, usize, String) {
+        let err = s.parse::<StringExpression>().unwrap_err();
+        (err.line(), err.column(), String::from(err.message()))
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error("Concat(SubStr(col0, ConstPos(0), ConstPos(2)))"),
+            (1, 30, String::from("occurrence must be nonzero"))
+        );
+        assert_eq!(
+            error("Concat(\n  SubStr(col0, Pos(Digit, 1, End), ConstPos(2)))"),
+            (2, 20, String::from("unknown token `Digit`"))
+        );
+        assert_eq!(
+            error("Concat(SubStr(col0, Pos(\"\", 1, End), ConstPos(2)))"),
+            (1, 25, String::from("literal token must be nonempty"))
+        );
+        assert_eq!(
+            error("Concat(Const(\"abc))"),
+            (1, 14, String::from("unterminated string"))
+        );
+        assert_eq!(
+            error("Concat(Const(\"a\\qc\"))"),
+            (1, 16, String::from("invalid escape sequence"))
+        );
+        assert_eq!(
+            error("Concat(SubStr(column0, ConstPos(1), ConstPos(2)))"),
+            (1, 15, String::from("expected a column, found `column0`"))
+        );
+        assert_eq!(
+            error("Concat(Const(\"a\") Const(\"b\"))"),
+            (1, 19, String::from("expected `)`, found `Const`"))
+        );
+        assert_eq!(
+            error("Concat() x"),
+            (1, 10, String::from("expected end of input, found `x`"))
+        );
+        assert_eq!(
+            error("Concat(Number(Const(\"1\"), 0, Keep, \"\"))"),
+            (1, 27, String::from("width must be positive"))
+        );
+        assert_eq!(
+            error("Concat(Number(Const(\"1\"), 65, Keep, \"\"))"),
+            (1, 27, String::from("width must be at most 64"))
+        );
+        assert_eq!(
+            error("Concat(Number(Const(\"1\"), 1, 1000000000, \"\"))"),
+            (1, 30, String::from("decimals must be at most 64"))
+        );
+        assert_eq!(
+            error("Concat(Number(Const(\"1\"), 1, Round, \"\"))"),
+            (1, 30, String::from("expected `Keep`, found `Round`"))
+        );
+        assert_eq!(
+            error("Concat(Number(Const(\"1\"), 1, 2, \", \"))"),
+            (
+                1,
+                33,
+                String::from("separator must be empty or a single character")
+            )
+        );
+        assert_eq!(
+            error("Concat(Date(Const(\"1/2\"), \"%m/%e\", \"%d\"))"),
+            (1, 27, String::from("unknown date directive `%e`"))
+        );
+        assert_eq!(
+            error("Concat(Integer(Sub(Parse(Const(\"1\")), 1), 1, Keep, \"\"))"),
+            (
+                1,
+                16,
+                String::from("expected an integer expression, found `Sub`")
+            )
+        );
+        assert_eq!(
+            error("Concat(SubStr(col0, Pos(CAPS, 1, Middle), ConstPos(2)))"),
+            (
+                1,
+                34,
+                String::from("expected `Start` or `End`, found `Middle`")
+            )
+        );
+    }
+
+    #[test]
+    fn nesting() {
+        let nested = |n| {
+            format!(
+                "Concat({}Const(\"a\"){})",
+                "Upper(".repeat(n),
+                ")".repeat(n)
+            )
+        };
+        assert!(nested(MAX_DEPTH - 1).parse::<StringExpression>().is_ok());
+        assert_eq!(
+            error(&nested(MAX_DEPTH)),
+            (
+                1,
+                8 + 6 * MAX_DEPTH,
+                format!("expressions are nested more than {} deep", MAX_DEPTH)
+            )
+        );
+        // deeply nested input gives an error instead of overflowing the stack
+        let n = 100_000;
+        let s = format!(
+            "Concat({}col0{})",
+            "SubStr(".repeat(n),
+            ", ConstPos(1), ConstPos(2))".repeat(n)
+        );
+        assert_eq!(error(&s).0, 1);
+        let s = format!(
+            "Concat(Integer({}Parse(Const(\"1\")){}, 1, Keep, \"\"))",
+            "Add(".repeat(n),
+            ", 1)".repeat(n)
+        );
+        assert_eq!(error(&s).0, 1);
+        let s = "Concat(Integer(Add(Parse(Const(\"1\")), -9223372036854775808), 1, Keep, \"\"))";
+        assert_eq!(s.parse::<StringExpression>().unwrap().to_string(), s);
+    }
+
+    #[test]
+    fn program() {
+        let s = "Concat(SubStr(col0, Pos(ProperCase, 1, Start), ConstPos(2)), Const(\".\"))";
+        let prog: Program = s.parse().unwrap();
+        assert_eq!(prog.to_string(), s);
+        let s =
+            "Switch((And(Match(col0, \"+\", 1), Match(col0, Digits, 4)), Concat(Const(\"+\"))), \
+                 (True, Concat(SubStr(col0, Pos(StartT, 1, End), Pos(Digits, 1, End)))))";
+        let prog: Program = s.parse().unwrap();
+        assert_eq!(prog.to_string(), s);
+        assert_eq!(prog.branches().len(), 2);
+        assert_eq!(prog.branches()[0].0.conditions()[1].count(), 4);
+        assert_eq!(prog.expression(), None);
+        // a switch with a single branch that always holds is the same as its expression
+        let prog: Program = "Switch((True, Concat(Const(\"a\"))))".parse().unwrap();
+        assert_eq!(prog.to_string(), "Concat(Const(\"a\"))");
+        assert_eq!(
+            "Switch((Match(col0, CAPS, -1), Concat()))"
+                .parse::<Program>()
+                .unwrap_err()
+                .to_string(),
+            "line 1, column 27: count must not be negative"
+        );
+        let s = "Concat(Lookup(\"months\", SubStr(col0, ConstPos(1), ConstPos(2))))";
+        assert_eq!(
+            s.parse::<Program>().unwrap_err().to_string(),
+            "line 1, column 15: unknown table `months`"
+        );
+        let prog = Program::parse_with_tables(s, &tables()).unwrap();
+        assert_eq!(prog.to_string(), s);
+        assert_eq!(prog.run(&["1st"]).unwrap(), "January");
+        let s = "Concat(SubStr(col0, Pos(Digits, 1, Start), Pos(Hex, 1, End)))";
+        assert_eq!(
+            s.parse::<Program>().unwrap_err().to_string(),
+            "line 1, column 48: unknown token `Hex`"
+        );
+        let hex = CustomToken::new("Hex", regex::Regex::new("0x[0-9a-f]+").unwrap(), 70).unwrap();
+        let prog = Program::parse_with(s, &[], &[hex]).unwrap();
+        assert_eq!(prog.to_string(), s);
+        assert_eq!(prog.run(&["at 0xff;"]).unwrap(), "0xff");
+        assert_eq!(
+            "Switch()".parse::<Program>().unwrap_err().to_string(),
+            "line 1, column 8: expected `(`, found `)`"
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! Sampling of unpaired rows, for learning from a bounded number of them.
+//!
+//! Rows are grouped by their format, which is the sequence of character classes in their columns,
+//! so that "(617) 253-1234" and "(204) 447-3924" have the same format but "617-253-1234" doesn't.
+//! Every format gets at least one row in the sample, so rare formats still shape the input data
+//! graph, and the rest of the sample is split among the formats in proportion to their number of
+//! rows. The rows of each format are chosen by reservoir sampling with a seeded generator, so the
+//! same rows and seed always give the same sample.
+
+use std::collections::HashMap;
+
+// the splitmix64 generator; it is defined here rather than taken from a crate so that samples
+// don't change between versions of a dependency
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // a number in 0..n, for n > 0
+    fn below(&mut self, n: usize) -> usize {
+        ((u128::from(self.next()) * n as u128) >> 64) as usize
+    }
+}
+
+// the format of a row: runs of digits, uppercase letters, lowercase letters, other letters, and
+// whitespace are each replaced by a single character, and everything else is kept
+fn format(row: &[&str]) -> String {
+    let mut format = String::new();
+    for (i, col) in row.iter().enumerate() {
+        if i > 0 {
+            // a separator that can't come from a column
+            format.push('\u{0}');
+        }
+        let mut prev = None;
+        for c in col.chars() {
+            let class = if c.is_numeric() {
+                '0'
+            } else if c.is_uppercase() {
+                'A'
+            } else if c.is_lowercase() {
+                'a'
+            } else if c.is_alphabetic() {
+                'x'
+            } else if c.is_whitespace() {
+                ' '
+            } else {
+                format.push(c);
+                prev = None;
+                continue;
+            };
+            if prev != Some(class) {
+                format.push(class);
+                prev = Some(class);
+            }
+        }
+    }
+    format
+}
+
+// chooses at most max of the rows, returning their indices in increasing order; all of the rows
+// are chosen if there are at most max of them
+pub fn sample(rows: &[Vec<&str>], max: usize, seed: u64) -> Vec<usize> {
+    if rows.len() <= max {
+        return (0..rows.len()).collect();
+    }
+    let mut rng = Rng(seed);
+    // the rows of each format, with the formats in the order they first appear in
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (i, row) in rows.iter().enumerate() {
+        let g = *index.entry(format(row)).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[g].push(i);
+    }
+
+    let mut quotas = vec![0; groups.len()];
+    if groups.len() >= max {
+        // there isn't room for every format, so a random subset of them gets one row each
+        for g in reservoir(0..groups.len(), max, &mut rng) {
+            quotas[g] = 1;
+        }
+    } else {
+        // every format gets one row, and the rest are split in proportion to the other rows of
+        // each format; this is at most the size of the format, because max < rows.len()
+        let (spare, others) = (max - groups.len(), rows.len() - groups.len());
+        for (quota, group) in quotas.iter_mut().zip(&groups) {
+            *quota = 1 + spare * (group.len() - 1) / others;
+        }
+        // rounding down leaves some rows over, which go to the largest formats that have room
+        let mut order: Vec<usize> = (0..groups.len()).collect();
+        order.sort_by_key(|g| std::cmp::Reverse(groups[*g].len()));
+        let mut left = max - quotas.iter().sum::<usize>();
+        for g in order.into_iter().cycle() {
+            if left == 0 {
+                break;
+            }
+            if quotas[g] < groups[g].len() {
+                quotas[g] += 1;
+                left -= 1;
+            }
+        }
+    }
+
+    let mut chosen: Vec<usize> = groups
+        .into_iter()
+        .zip(quotas)
+        .flat_map(|(group, quota)| reservoir(group.into_iter(), quota, &mut rng))
+        .collect();
+    chosen.sort_unstable();
+    chosen
+}
+
+// chooses k of the items uniformly at random (algorithm R)
+fn reservoir(items: impl Iterator<Item = usize>, k: usize, rng: &mut Rng) -> Vec<usize> {
+    let mut chosen = Vec::with_capacity(k);
+    for (i, item) in items.enumerate() {
+        if i < k {
+            chosen.push(item);
+        } else {
+            let j = rng.below(i + 1);
+            if j < k {
+                chosen[j] = item;
+            }
+        }
+    }
+    chosen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        assert_eq!(format(&["(617) 253-1234"]), "(0) 0-0");
+        assert_eq!(format(&["Ben Bitdiddle", "2002"]), "Aa Aa\u{0}0");
+        assert_eq!(format(&["東京都 100"]), "x 0");
+        assert_eq!(format(&["(204) 447-3924"]), format(&["(617) 253-1234"]));
+    }
+
+    #[test]
+    fn stratified() {
+        let mut data: Vec<String> = (0..1000).map(|i| format!("{}-{}", i, i * 7)).collect();
+        data.push(String::from("(617) 253-1234"));
+        data.extend((0..99).map(|i| format!("Unit {}", i)));
+        let rows: Vec<Vec<&str>> = data.iter().map(|s| vec![s.as_str()]).collect();
+        let chosen = sample(&rows, 50, 1);
+        assert_eq!(chosen.len(), 50);
+        assert!(chosen.windows(2).all(|w| w[0] < w[1]));
+        // the rare format is kept, and the others are sampled in proportion
+        assert!(chosen.contains(&1000));
+        assert_eq!(chosen.iter().filter(|i| **i < 1000).count(), 44);
+        // the same seed gives the same sample, and other seeds give other samples
+        assert_eq!(sample(&rows, 50, 1), chosen);
+        assert_ne!(sample(&rows, 50, 2), chosen);
+        // everything is kept when there is room for it
+        assert_eq!(sample(&rows[..10], 10, 1), (0..10).collect::<Vec<_>>());
+        // with more formats than room, every chosen row has a different format
+        let chosen = sample(&rows, 2, 3);
+        assert_eq!(chosen.len(), 2);
+        assert_ne!(format(&rows[chosen[0]]), format(&rows[chosen[1]]));
+        assert!(sample(&rows, 0, 3).is_empty());
+    }
+}
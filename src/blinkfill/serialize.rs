@@ -0,0 +1,537 @@
+//! Serialization of learned programs, enabled with the `serde` feature.
+//!
+//! Programs are serialized along with a format version, so that programs saved with an older
+//! version of this crate can still be loaded after the language changes. Tokens are serialized by
+//! name rather than by their position in the `Token` enum, so adding tokens does not change the
+//! meaning of saved programs. Custom tokens are serialized by name along with their pattern and
+//! weight, and lookup tables are serialized along with their entries, so a saved
+//! program can be run without the tables it was learned with, and date formats are serialized as
+//! their patterns, such as `"%d/%m/%Y"`.
+
+use super::date::DateFormat;
+use super::language::{Occurrence, Predicate, Program, StringExpression};
+use super::number::{NumberFormat, MAX_DIGITS};
+use super::table::Table;
+use super::token::{CustomToken, Token};
+use regex::Regex;
+use serde::de::{self, MapAccess, SeqAccess, Unexpected, Visitor};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_value::Value;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The format version written by this version of the crate.
+///
+/// Version 1 stored a single `expression`; version 2 stores a list of `branches`, each of which is
+/// a predicate and an expression, to support conditional programs.
+const FORMAT_VERSION: u32 = 2;
+
+type Branches = Vec<(Predicate, StringExpression)>;
+
+impl Serialize for Program {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Program", 2)?;
+        state.serialize_field("version", &FORMAT_VERSION)?;
+        state.serialize_field("branches", self.branches())?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "lowercase")]
+enum Field {
+    Version,
+    Expression,
+    Branches,
+}
+
+struct ProgramVisitor;
+
+impl ProgramVisitor {
+    fn check_version<E: de::Error>(version: u32) -> Result<(), E> {
+        if version == 0 || version > FORMAT_VERSION {
+            return Err(E::invalid_value(
+                Unexpected::Unsigned(version.into()),
+                &"a supported program format version",
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_branches<E: de::Error>(branches: Branches) -> Result<Program, E> {
+        Program::switch(branches).ok_or_else(|| E::invalid_length(0, &"at least one branch"))
+    }
+}
+
+impl<'de> Visitor<'de> for ProgramVisitor {
+    type Value = Program;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("struct Program")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Program, A::Error> {
+        let version: u32 = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        Self::check_version(version)?;
+        if version == 1 {
+            let expr: StringExpression = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+            return Ok(Program::new(expr));
+        }
+        let branches: Branches = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        Self::check_branches(branches)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Program, A::Error> {
+        // the version determines how the rest is parsed, but it doesn't have to come first (formats
+        // can reorder keys, such as `serde_json::Value`, which sorts them), so the other fields are
+        // buffered until the whole map has been read
+        let mut version: Option<u32> = None;
+        let mut expression: Option<Value> = None;
+        let mut branches: Option<Value> = None;
+        while let Some(key) = map.next_key()? {
+            let (slot, name) = match key {
+                Field::Version => {
+                    if version.is_some() {
+                        return Err(de::Error::duplicate_field("version"));
+                    }
+                    version = Some(map.next_value()?);
+                    continue;
+                }
+                Field::Expression => (&mut expression, "expression"),
+                Field::Branches => (&mut branches, "branches"),
+            };
+            if slot.is_some() {
+                return Err(de::Error::duplicate_field(name));
+            }
+            *slot = Some(map.next_value()?);
+        }
+        let version = version.ok_or_else(|| de::Error::missing_field("version"))?;
+        Self::check_version(version)?;
+        if version == 1 {
+            // version 1 programs have an expression, which becomes a single branch
+            if branches.is_some() {
+                return Err(de::Error::unknown_field(
+                    "branches",
+                    &["version", "expression"],
+                ));
+            }
+            let expr: StringExpression =
+                decode(expression.ok_or_else(|| de::Error::missing_field("expression"))?)?;
+            return Self::check_branches(vec![(Predicate::default(), expr)]);
+        }
+        if expression.is_some() {
+            return Err(de::Error::unknown_field(
+                "expression",
+                &["version", "branches"],
+            ));
+        }
+        let branches = decode(branches.ok_or_else(|| de::Error::missing_field("branches"))?)?;
+        Self::check_branches(branches)
+    }
+}
+
+// decodes a buffered field
+fn decode<'de, T: Deserialize<'de>, E: de::Error>(value: Value) -> Result<T, E> {
+    value.deserialize_into().map_err(E::custom)
+}
+
+impl<'de> Deserialize<'de> for Program {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct(
+            "Program",
+            &["version", "expression", "branches"],
+            ProgramVisitor,
+        )
+    }
+}
+
+impl Serialize for Occurrence {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Occurrence {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let k = isize::deserialize(deserializer)?;
+        if k == 0 {
+            return Err(de::Error::invalid_value(
+                Unexpected::Signed(0),
+                &"a nonzero occurrence",
+            ));
+        }
+        Ok(Occurrence(k))
+    }
+}
+
+// the on-disk representation of a number format
+#[derive(Deserialize)]
+#[serde(rename = "NumberFormat")]
+struct NumberFormatRepr {
+    width: usize,
+    decimals: Option<usize>,
+    separator: Option<char>,
+}
+
+impl<'de> Deserialize<'de> for NumberFormat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = NumberFormatRepr::deserialize(deserializer)?;
+        // the limits keep formatting from taking unbounded time and memory
+        if repr.width == 0 || repr.width > MAX_DIGITS {
+            return Err(de::Error::invalid_value(
+                Unexpected::Unsigned(repr.width as u64),
+                &format!("a width from 1 to {}", MAX_DIGITS).as_str(),
+            ));
+        }
+        if let Some(decimals) = repr.decimals.filter(|d| *d > MAX_DIGITS) {
+            return Err(de::Error::invalid_value(
+                Unexpected::Unsigned(decimals as u64),
+                &format!("at most {} decimals", MAX_DIGITS).as_str(),
+            ));
+        }
+        Ok(NumberFormat {
+            width: repr.width,
+            decimals: repr.decimals,
+            separator: repr.separator,
+        })
+    }
+}
+
+// the on-disk representation of a token
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "Token")]
+enum TokenRepr<'a> {
+    Named(Cow<'a, str>),
+    Literal(Cow<'a, str>),
+    Custom {
+        name: Cow<'a, str>,
+        pattern: Cow<'a, str>,
+        weight: usize,
+    },
+}
+
+impl Serialize for Token {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let repr = match (self.literal(), self.custom()) {
+            (Some(s), _) => TokenRepr::Literal(Cow::Borrowed(s)),
+            (_, Some(custom)) => TokenRepr::Custom {
+                name: Cow::Borrowed(custom.name()),
+                pattern: Cow::Borrowed(custom.regex().as_str()),
+                weight: custom.weight(),
+            },
+            (None, None) => TokenRepr::Named(Cow::Borrowed(self.name().unwrap())),
+        };
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Token {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match TokenRepr::deserialize(deserializer)? {
+            TokenRepr::Named(name) => Token::from_name(&name).ok_or_else(|| {
+                de::Error::invalid_value(Unexpected::Str(&name), &"the name of a token")
+            }),
+            TokenRepr::Literal(s) => Token::literal_from(&s).ok_or_else(|| {
+                de::Error::invalid_value(Unexpected::Str(&s), &"a nonempty literal")
+            }),
+            TokenRepr::Custom {
+                name,
+                pattern,
+                weight,
+            } => {
+                let regex = Regex::new(&pattern).map_err(|_| {
+                    de::Error::invalid_value(Unexpected::Str(&pattern), &"a regular expression")
+                })?;
+                CustomToken::new(name.as_ref(), regex, weight)
+                    .map(Token::Custom)
+                    .map_err(|_| {
+                        de::Error::invalid_value(Unexpected::Str(&name), &"a custom token name")
+                    })
+            }
+        }
+    }
+}
+
+impl Serialize for DateFormat {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for DateFormat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = Cow::<str>::deserialize(deserializer)?;
+        DateFormat::from_pattern(&pattern)
+            .map_err(|_| de::Error::invalid_value(Unexpected::Str(&pattern), &"a date format"))
+    }
+}
+
+// the on-disk representation of a table
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "Table")]
+struct TableRepr<'a> {
+    name: Cow<'a, str>,
+    entries: BTreeMap<Cow<'a, str>, Cow<'a, str>>,
+}
+
+impl Serialize for Table {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let repr = TableRepr {
+            name: Cow::Borrowed(self.name()),
+            entries: self
+                .entries()
+                .map(|(k, v)| (Cow::Borrowed(k), Cow::Borrowed(v)))
+                .collect(),
+        };
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Table {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = TableRepr::deserialize(deserializer)?;
+        Ok(Table::new(repr.name, repr.entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::language::{
+        Case, ColumnIndex, Condition, Direction, Position, SubstringExpression,
+    };
+    use super::*;
+    use crate::StringProgram;
+
+    fn example() -> Program {
+        Program::new(StringExpression(vec![
+            SubstringExpression::Substring(
+                ColumnIndex(0),
+                Position::Match(
+                    Token::Literal(String::from(", ")),
+                    Occurrence(1),
+                    Direction::End,
+                ),
+                Position::Match(Token::End, Occurrence(1), Direction::Start),
+            ),
+            SubstringExpression::ConstantString(String::from("!")),
+            SubstringExpression::Substring(
+                ColumnIndex(1),
+                Position::ConstantPosition(Occurrence(2)),
+                Position::Match(Token::Digits, Occurrence(-1), Direction::End),
+            ),
+            SubstringExpression::Convert(
+                Case::Upper,
+                Box::new(SubstringExpression::Substring(
+                    ColumnIndex(1),
+                    Position::ConstantPosition(Occurrence(1)),
+                    Position::ConstantPosition(Occurrence(2)),
+                )),
+            ),
+        ]))
+    }
+
+    #[test]
+    fn round_trip() {
+        let prog = example();
+        let json = serde_json::to_string(&prog).unwrap();
+        let loaded: Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, prog);
+        assert_eq!(
+            loaded.run(&["Mumbai, India", "x1985"]).unwrap(),
+            "India!1985X"
+        );
+    }
+
+    fn switch_example() -> Program {
+        let condition = Condition {
+            column: ColumnIndex(1),
+            token: Token::Literal(String::from("x")),
+            count: 1,
+        };
+        Program::switch(vec![
+            (
+                Predicate(vec![condition]),
+                example().expression().unwrap().clone(),
+            ),
+            (
+                Predicate::default(),
+                StringExpression(vec![SubstringExpression::ConstantString(String::from("?"))]),
+            ),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn round_trip_switch() {
+        let prog = switch_example();
+        let json = serde_json::to_string(&prog).unwrap();
+        let loaded: Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, prog);
+        assert_eq!(loaded.run(&["Mumbai, India", "1985"]).unwrap(), "?");
+    }
+
+    #[test]
+    fn round_trip_lookup() {
+        let table = Table::new("months", vec![("3", "March"), ("4", "April")]);
+        let prog = Program::new(StringExpression(vec![SubstringExpression::Lookup(
+            table,
+            Box::new(SubstringExpression::Substring(
+                ColumnIndex(0),
+                Position::ConstantPosition(Occurrence(1)),
+                Position::Match(Token::End, Occurrence(1), Direction::Start),
+            )),
+        )]));
+        let json = serde_json::to_value(&prog).unwrap();
+        let table = &json["branches"][0][1][0]["Lookup"][0];
+        assert_eq!(table["name"], "months");
+        assert_eq!(table["entries"]["4"], "April");
+        // the loaded program doesn't need the original table
+        let json = serde_json::to_string(&prog).unwrap();
+        let loaded: Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, prog);
+        assert_eq!(loaded.run(&["4"]).unwrap(), "April");
+    }
+
+    #[test]
+    fn round_trip_date() {
+        let prog = Program::new(StringExpression(vec![SubstringExpression::Date(
+            DateFormat::from_pattern("%d/%m/%Y").unwrap(),
+            DateFormat::from_pattern("%B %-d, %Y").unwrap(),
+            Box::new(SubstringExpression::Substring(
+                ColumnIndex(0),
+                Position::ConstantPosition(Occurrence(1)),
+                Position::Match(Token::End, Occurrence(1), Direction::Start),
+            )),
+        )]));
+        let json = serde_json::to_value(&prog).unwrap();
+        assert_eq!(json["branches"][0][1][0]["Date"][0], "%d/%m/%Y");
+        let json = serde_json::to_string(&prog).unwrap();
+        let loaded: Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, prog);
+        assert_eq!(loaded.run(&["03/07/2021"]).unwrap(), "July 3, 2021");
+        let json = json.replace("%B", "%Q");
+        assert!(serde_json::from_str::<Program>(&json).is_err());
+    }
+
+    #[test]
+    fn round_trip_custom_token() {
+        let sku = CustomToken::new("SKU", Regex::new(r"[A-Z]{2}-\d{4}").unwrap(), 90).unwrap();
+        let prog = Program::new(StringExpression(vec![SubstringExpression::Substring(
+            ColumnIndex(0),
+            Position::Match(Token::Custom(sku.clone()), Occurrence(1), Direction::Start),
+            Position::Match(Token::Custom(sku), Occurrence(1), Direction::End),
+        )]));
+        let json = serde_json::to_value(&prog).unwrap();
+        let token = &json["branches"][0][1][0]["Substring"][1]["Match"][0]["Custom"];
+        assert_eq!(token["name"], "SKU");
+        assert_eq!(token["pattern"], r"[A-Z]{2}-\d{4}");
+        assert_eq!(token["weight"], 90);
+        // the loaded program doesn't need the original token
+        let json = serde_json::to_string(&prog).unwrap();
+        let loaded: Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, prog);
+        assert_eq!(loaded.run(&["order AB-1234"]).unwrap(), "AB-1234");
+        let json = json.replace(r"{2}", r"{2");
+        assert!(serde_json::from_str::<Program>(&json).is_err());
+        let json = serde_json::to_string(&prog)
+            .unwrap()
+            .replace("SKU", "Digits");
+        assert!(serde_json::from_str::<Program>(&json).is_err());
+    }
+
+    #[test]
+    fn format() {
+        let json = serde_json::to_value(switch_example()).unwrap();
+        assert_eq!(json["version"], FORMAT_VERSION);
+        let condition = &json["branches"][0][0][0];
+        assert_eq!(condition["column"], 1);
+        assert_eq!(condition["token"]["Literal"], "x");
+        assert_eq!(condition["count"], 1);
+        assert_eq!(json["branches"][1][0], serde_json::json!([]));
+        let parts = &json["branches"][0][1];
+        assert_eq!(parts[0]["Substring"][1]["Match"][0]["Literal"], ", ");
+        assert_eq!(parts[0]["Substring"][2]["Match"][0]["Named"], "EndT");
+        assert_eq!(parts[2]["Substring"][2]["Match"][1], -1);
+        assert_eq!(parts[3]["Convert"][0], "Upper");
+    }
+
+    #[test]
+    fn rejects_unsupported_version() {
+        let json = r#"{"version": 1000, "expression": []}"#;
+        assert!(serde_json::from_str::<Program>(json).is_err());
+        let json = r#"{"expression": [], "version": 1000}"#;
+        assert!(serde_json::from_str::<Program>(json).is_err());
+        let json = r#"{"branches": [], "version": 1}"#;
+        assert!(serde_json::from_str::<Program>(json).is_err());
+        let json = r#"{"version": 1, "expression": [], "expression": []}"#;
+        assert!(serde_json::from_str::<Program>(json).is_err());
+        let json = r#"{"version": 2, "expression": []}"#;
+        assert!(serde_json::from_str::<Program>(json).is_err());
+        let json = r#"{"version": 1, "branches": []}"#;
+        assert!(serde_json::from_str::<Program>(json).is_err());
+    }
+
+    #[test]
+    fn loads_version_1() {
+        let json = r#"{"version": 1, "expression": [{"ConstantString": "a"}]}"#;
+        let prog: Program = serde_json::from_str(json).unwrap();
+        assert_eq!(prog.to_string(), "Concat(Const(\"a\"))");
+        // the version doesn't have to come first
+        let json = r#"{"expression": [{"ConstantString": "a"}], "version": 1}"#;
+        assert_eq!(serde_json::from_str::<Program>(json).unwrap(), prog);
+    }
+
+    #[test]
+    fn round_trip_value() {
+        // `serde_json::Value` sorts keys, so the version comes after the branches
+        let prog = switch_example();
+        let value = serde_json::to_value(&prog).unwrap();
+        let loaded: Program = serde_json::from_value(value).unwrap();
+        assert_eq!(loaded, prog);
+        assert_eq!(
+            loaded.run(&["Mumbai, India", "x1985"]).unwrap(),
+            "India!1985X"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_programs() {
+        let json = r#"{"version": 1, "expression": [
+            {"Substring": [0, {"Match": [{"Literal": "|"}, 1, "End"]}, {"ConstantPosition": 3}]}
+        ]}"#;
+        assert!(serde_json::from_str::<Program>(json).is_ok());
+        let json = r#"{"version": 1, "expression": [
+            {"Substring": [0, {"ConstantPosition": 0}, {"ConstantPosition": 3}]}
+        ]}"#;
+        assert!(serde_json::from_str::<Program>(json).is_err());
+        let json = r#"{"version": 1, "expression": [
+            {"Substring": [0, {"Match": [{"Literal": ""}, 1, "End"]}, {"ConstantPosition": 3}]}
+        ]}"#;
+        assert!(serde_json::from_str::<Program>(json).is_err());
+        let json = r#"{"version": 1, "expression": [
+            {"Substring": [0, {"Match": [{"Named": "Bogus"}, 1, "End"]}, {"ConstantPosition": 3}]}
+        ]}"#;
+        assert!(serde_json::from_str::<Program>(json).is_err());
+        let json = r#"{"version": 2, "branches": []}"#;
+        assert!(serde_json::from_str::<Program>(json).is_err());
+        let number = |width, decimals| {
+            let format =
+                serde_json::json!({"width": width, "decimals": decimals, "separator": null});
+            let expr = serde_json::json!({"ConstantString": "7"});
+            serde_json::json!({"version": 2, "branches": [[[], [{"Number": [format, expr]}]]]})
+        };
+        let prog = serde_json::from_value::<Program>(number(3, 2)).unwrap();
+        assert_eq!(prog.run(&[""]).unwrap(), "007.00");
+        assert!(serde_json::from_value::<Program>(number(0, 2)).is_err());
+        assert!(serde_json::from_value::<Program>(number(1_000_000_000, 2)).is_err());
+        assert!(serde_json::from_value::<Program>(number(3, 1_000_000_000)).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+// A suffix array with longest common prefixes, for finding the repeated substrings of a string
+// without comparing every pair of substrings.
+
+pub struct SuffixArray {
+    // the starts of the suffixes, in sorted order
+    suffixes: Vec<usize>,
+    // lcp[k] is the length of the longest common prefix of the suffixes at k - 1 and k, and
+    // lcp[0] is 0
+    lcp: Vec<usize>,
+}
+
+impl SuffixArray {
+    pub fn new<T: Ord>(s: &[T]) -> Self {
+        let n = s.len();
+        // sort by prefixes of doubling width, ranking suffixes by their prefixes so far
+        let mut suffixes: Vec<usize> = (0..n).collect();
+        suffixes.sort_by(|a, b| s[*a].cmp(&s[*b]));
+        let mut rank = vec![0; n];
+        for k in 1..n {
+            let (prev, curr) = (suffixes[k - 1], suffixes[k]);
+            rank[curr] = rank[prev] + usize::from(s[prev] != s[curr]);
+        }
+        let mut width = 1;
+        while width < n && rank[suffixes[n - 1]] < n - 1 {
+            // a suffix that ends within the width sorts before the longer ones with its prefix
+            let key = |i: usize| (rank[i], rank.get(i + width).map(|r| r + 1).unwrap_or(0));
+            suffixes.sort_by_key(|i| key(*i));
+            let mut next = vec![0; n];
+            for k in 1..n {
+                let (prev, curr) = (suffixes[k - 1], suffixes[k]);
+                next[curr] = next[prev] + usize::from(key(prev) != key(curr));
+            }
+            rank = next;
+            width *= 2;
+        }
+
+        // Kasai's algorithm: the common prefix with the previous suffix shrinks by at most one
+        // from each suffix to the next one in the string
+        for (k, i) in suffixes.iter().enumerate() {
+            rank[*i] = k;
+        }
+        let mut lcp = vec![0; n];
+        let mut h: usize = 0;
+        for i in 0..n {
+            if rank[i] == 0 {
+                h = 0;
+                continue;
+            }
+            let j = suffixes[rank[i] - 1];
+            while i + h < n && j + h < n && s[i + h] == s[j + h] {
+                h += 1;
+            }
+            lcp[rank[i]] = h;
+            h = h.saturating_sub(1);
+        }
+        Self { suffixes, lcp }
+    }
+
+    // groups the starts of the substrings with the given length by their contents; each group is
+    // in increasing order, and the groups are in the order of the substrings
+    pub fn equal_substrings(&self, len: usize) -> Vec<Vec<usize>> {
+        let n = self.suffixes.len();
+        let mut groups = Vec::new();
+        let mut group = Vec::new();
+        for (k, i) in self.suffixes.iter().enumerate() {
+            // suffixes that are shorter than len have a shorter common prefix with their
+            // neighbors, so they never join a group
+            if self.lcp[k] < len && !group.is_empty() {
+                group.sort_unstable();
+                groups.push(group);
+                group = Vec::new();
+            }
+            if n - i >= len {
+                group.push(*i);
+            }
+        }
+        if !group.is_empty() {
+            group.sort_unstable();
+            groups.push(group);
+        }
+        groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suffixes() {
+        let s: Vec<char> = "banana".chars().collect();
+        let sa = SuffixArray::new(&s);
+        assert_eq!(sa.suffixes, vec![5, 3, 1, 0, 4, 2]);
+        assert_eq!(sa.lcp, vec![0, 1, 3, 0, 0, 2]);
+        assert!(SuffixArray::new::<char>(&[]).suffixes.is_empty());
+    }
+
+    #[test]
+    fn equal_substrings() {
+        let s: Vec<char> = "banana".chars().collect();
+        let sa = SuffixArray::new(&s);
+        assert_eq!(
+            sa.equal_substrings(1),
+            vec![vec![1, 3, 5], vec![0], vec![2, 4]]
+        );
+        assert_eq!(sa.equal_substrings(3), vec![vec![1, 3], vec![0], vec![2]]);
+        assert_eq!(sa.equal_substrings(6), vec![vec![0]]);
+        assert_eq!(sa.equal_substrings(7), Vec::<Vec<usize>>::new());
+        // every substring is in exactly one group, with the other occurrences of its contents
+        let s: Vec<char> = "abcabxabcab é é".chars().collect();
+        let sa = SuffixArray::new(&s);
+        for len in 1..s.len() + 1 {
+            let groups = sa.equal_substrings(len);
+            assert_eq!(
+                groups.iter().map(Vec::len).sum::<usize>(),
+                s.len() + 1 - len
+            );
+            for group in groups {
+                for i in &group {
+                    assert_eq!(s[*i..*i + len], s[group[0]..group[0] + len]);
+                }
+            }
+        }
+    }
+}
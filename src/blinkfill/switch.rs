@@ -0,0 +1,231 @@
+//! Learning of conditional programs, for inputs in several formats.
+//!
+//! This follows the approach of the Flash Fill paper: the examples are partitioned into groups
+//! that can each be handled by a single string expression, and then a predicate is learned for
+//! every group that tells its inputs apart from the inputs of the other groups.
+
+use super::dag::Dag;
+use super::input_data_graph::InputDataGraph;
+use super::language::{ColumnIndex, Condition, Predicate, Program, StringExpression};
+use super::table::Table;
+use super::token::{Token, ALL_RE_TOKENS};
+use super::{Example, Ranking};
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+
+// learns a conditional program from examples that no single string expression is consistent with
+pub fn learn(
+    examples: &[Example],
+    unpaired: &[Vec<&str>],
+    ranking: Ranking,
+    tables: &[Table],
+    custom_tokens: &[Token],
+) -> Option<Program> {
+    let groups = partition(examples, tables, custom_tokens);
+    let inputs = |group: &[usize]| -> Vec<Vec<&str>> {
+        group.iter().map(|i| examples[*i].0.clone()).collect()
+    };
+
+    // the last group handles all the inputs that the other predicates don't match, so it doesn't
+    // need a predicate of its own
+    let mut predicates = Vec::with_capacity(groups.len());
+    for (i, group) in groups.iter().enumerate().take(groups.len() - 1) {
+        let negative: Vec<usize> = groups[i + 1..].iter().flatten().cloned().collect();
+        predicates.push(classify(&inputs(group), &inputs(&negative), custom_tokens)?);
+    }
+    predicates.push(Predicate::default());
+
+    // learn an expression for every group, using the unpaired inputs that go to the group's branch
+    // to pick one that generalizes
+    let mut group_unpaired = vec![vec![]; groups.len()];
+    for row in unpaired {
+        if let Some(i) = predicates.iter().position(|pred| pred.matches(row)) {
+            group_unpaired[i].push(row.clone());
+        }
+    }
+    let mut branches = Vec::with_capacity(groups.len());
+    for ((group, predicate), unpaired) in groups.iter().zip(predicates).zip(group_unpaired) {
+        let paired: Vec<Example> = group.iter().map(|i| examples[*i].clone()).collect();
+        let expr =
+            learn_expression(&paired, &unpaired, ranking, tables, custom_tokens).or_else(|| {
+                // the unpaired inputs can make the graph too coarse for the examples, so fall back
+                // to the examples alone
+                learn_expression(&paired, &[], ranking, tables, custom_tokens)
+            })?;
+        branches.push((predicate, expr));
+    }
+    Program::switch(branches)
+}
+
+// learns the top-ranked string expression for a set of examples
+fn learn_expression(
+    paired: &[Example],
+    unpaired: &[Vec<&str>],
+    ranking: Ranking,
+    tables: &[Table],
+    custom_tokens: &[Token],
+) -> Option<StringExpression> {
+    let graph = graph(paired, unpaired, custom_tokens);
+    Dag::learn(paired, &graph, tables).top_ranked_expression(&graph, ranking)
+}
+
+// builds the input data graph, with the example inputs first, as required by Dag::learn
+pub fn graph(
+    paired: &[Example],
+    unpaired: &[Vec<&str>],
+    custom_tokens: &[Token],
+) -> InputDataGraph {
+    let all: Vec<Vec<&str>> = paired
+        .iter()
+        .map(|(row, _)| row.clone())
+        .chain(unpaired.iter().cloned())
+        .collect();
+    InputDataGraph::new(&all, custom_tokens)
+}
+
+// partitions the examples into groups that each have a consistent string expression; examples
+// are added to the first group that they are consistent with, in order
+fn partition(examples: &[Example], tables: &[Table], custom_tokens: &[Token]) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = vec![];
+    for i in 0..examples.len() {
+        let fits = groups.iter().position(|group| {
+            let paired: Vec<Example> = group
+                .iter()
+                .chain(Some(&i))
+                .map(|j| examples[*j].clone())
+                .collect();
+            Dag::learn(&paired, &graph(&paired, &[], custom_tokens), tables).has_program()
+        });
+        match fits {
+            Some(g) => groups[g].push(i),
+            None => groups.push(vec![i]),
+        }
+    }
+    groups
+}
+
+// learns a predicate that matches all of the positive inputs and none of the negative inputs;
+// the predicate is a conjunction of conditions, which are chosen greedily to rule out as many of
+// the remaining negative inputs as possible
+fn classify(
+    positive: &[Vec<&str>],
+    negative: &[Vec<&str>],
+    custom_tokens: &[Token],
+) -> Option<Predicate> {
+    // candidate tokens are the regex tokens (including the custom ones), along with the literals
+    // that all of the positive inputs have in common
+    let graph = InputDataGraph::new(positive, custom_tokens);
+    let literals: BTreeSet<&Token> = graph
+        .tokens
+        .values()
+        .flatten()
+        .map(|(tok, _)| graph.interner.token(*tok))
+        .filter(|tok| tok.literal().is_some())
+        .collect();
+    let cols = positive[0].len();
+    let mut candidates = vec![];
+    for col in 0..cols {
+        let tokens = ALL_RE_TOKENS.iter().chain(custom_tokens);
+        for token in tokens.chain(literals.iter().cloned()) {
+            // the largest count that all of the positive inputs satisfy
+            let count = positive
+                .iter()
+                .map(|row| token.all_matches(row[col]).len())
+                .min()
+                .unwrap();
+            if count > 0 {
+                candidates.push(Condition {
+                    column: ColumnIndex(col),
+                    token: token.clone(),
+                    count,
+                });
+            }
+        }
+    }
+
+    let mut remaining: Vec<&Vec<&str>> = negative.iter().collect();
+    let mut conditions = vec![];
+    while !remaining.is_empty() {
+        // prefer conditions that rule out more inputs, and then more general tokens; regex tokens
+        // are more general than literals, and shorter literals are more general than longer ones,
+        // which are more likely to only match the examples
+        let generality = |token: &Token| match token.literal() {
+            Some(s) => (0, Reverse(s.len())),
+            None => (1, Reverse(0)),
+        };
+        let best = candidates
+            .iter()
+            .map(|c| {
+                let excluded = remaining.iter().filter(|row| !c.matches(row)).count();
+                (excluded, (generality(&c.token), c.token.weight()), c)
+            })
+            .filter(|(excluded, _, _)| *excluded > 0)
+            .fold(None, |best: Option<(usize, _, &Condition)>, x| match best {
+                Some(b) if (b.0, b.1) >= (x.0, x.1) => Some(b),
+                _ => Some(x),
+            })?;
+        let condition = best.2.clone();
+        remaining.retain(|row| condition.matches(row));
+        conditions.push(condition);
+    }
+    Some(Predicate(conditions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StringProgram;
+
+    #[test]
+    fn partition_formats() {
+        let examples: Vec<Example> = vec![
+            (vec!["Hacker, Alyssa"], "Alyssa Hacker"),
+            (vec!["Ben Bitdiddle"], "Ben Bitdiddle"),
+            (vec!["Reasoner, Louis"], "Louis Reasoner"),
+            (vec!["Eva Ator"], "Eva Ator"),
+        ];
+        assert_eq!(partition(&examples, &[], &[]), vec![vec![0, 2], vec![1, 3]]);
+    }
+
+    #[test]
+    fn classify_inputs() {
+        let positive = vec![vec!["+1 234-567-8901"], vec!["+44 20-7946-0958"]];
+        let negative = vec![vec!["(123) 456-7890"], vec!["617-253-1234"]];
+        let pred = classify(&positive, &negative, &[]).unwrap();
+        for row in &positive {
+            assert!(pred.matches(row));
+        }
+        for row in &negative {
+            assert!(!pred.matches(row));
+        }
+        // identical inputs can't be told apart
+        assert_eq!(classify(&positive, &positive, &[]), None);
+    }
+
+    #[test]
+    fn classify_prefers_general_tokens() {
+        // the whole input is a literal that tells the inputs apart, but only matches that input
+        let positive = vec![vec!["03/07/2021"]];
+        let negative = vec![vec!["Aug 4, 2021"], vec!["2021-09-05"]];
+        let pred = classify(&positive, &negative, &[]).unwrap();
+        assert_eq!(pred.to_string(), "Match(col0, \"/\", 2)");
+        assert!(pred.matches(&["25/12/2020"]));
+    }
+
+    #[test]
+    fn learn_switch() {
+        let examples: Vec<Example> = vec![
+            (vec!["Hacker, Alyssa P."], "Alyssa Hacker"),
+            (vec!["Ben Bitdiddle"], "Ben Bitdiddle"),
+            (vec!["Reasoner, Louis"], "Louis Reasoner"),
+        ];
+        let unpaired = vec![vec!["Ator, Eva Lu"], vec!["Cy D. Fect"]];
+        let prog = learn(&examples, &unpaired, Ranking::Paper, &[], &[]).unwrap();
+        assert_eq!(prog.branches().len(), 2);
+        for (input, output) in &examples {
+            assert_eq!(prog.run(input).as_deref(), Some(*output));
+        }
+        assert_eq!(prog.run(&["Tweakit, Lem E."]).unwrap(), "Lem Tweakit");
+        assert_eq!(prog.run(&["Louis Reasoner"]).unwrap(), "Louis Reasoner");
+    }
+}
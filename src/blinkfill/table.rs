@@ -0,0 +1,126 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// A named lookup table, for semantic transformations like mapping "3" to "March".
+///
+/// Tables are passed to the learner with [`Learner::table`](super::Learner::table), and learned
+/// programs look up substrings of the input in them. A table is cheap to clone, because its entries
+/// are shared.
+///
+/// # Example
+///
+/// ```
+/// use synox::blinkfill::Table;
+///
+/// let table = Table::new("months", vec![("1", "January"), ("2", "February")]);
+/// assert_eq!(table.name(), "months");
+/// assert_eq!(table.get("2"), Some("February"));
+/// assert_eq!(table.get("13"), None);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Table {
+    name: String,
+    entries: Arc<BTreeMap<String, String>>,
+    // the keys for every value, for finding the keys that map to a substring of the output
+    keys: Arc<BTreeMap<String, Vec<String>>>,
+}
+
+impl Table {
+    /// Creates a table with the given name and entries.
+    ///
+    /// If there are several entries with the same key, the last one wins.
+    pub fn new<I, K, V>(name: impl Into<String>, entries: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let entries: BTreeMap<String, String> = entries
+            .into_iter()
+            .map(|(k, v)| (k.into(), v.into()))
+            .collect();
+        let mut keys: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (k, v) in &entries {
+            keys.entry(v.clone()).or_default().push(k.clone());
+        }
+        Self {
+            name: name.into(),
+            entries: Arc::new(entries),
+            keys: Arc::new(keys),
+        }
+    }
+
+    /// Returns the name of the table.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Looks up a key in the table.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).map(|v| v.as_str())
+    }
+
+    /// Returns the entries of the table, in order of their keys.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    // returns the keys that map to the given value
+    pub(crate) fn keys_for(&self, value: &str) -> &[String] {
+        self.keys.get(value).map_or(&[], |keys| keys.as_slice())
+    }
+}
+
+// tables are compared by name and entries; the reverse index is determined by the entries, and
+// programs usually share the same entries, so those are compared by pointer first
+
+impl PartialEq for Table {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Table {}
+
+impl PartialOrd for Table {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Table {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.name.cmp(&other.name).then_with(|| {
+            if Arc::ptr_eq(&self.entries, &other.entries) {
+                Ordering::Equal
+            } else {
+                self.entries.cmp(&other.entries)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_for() {
+        let table = Table::new("t", vec![("a", "x"), ("b", "y"), ("c", "x"), ("b", "z")]);
+        assert_eq!(table.keys_for("x"), &["a", "c"]);
+        assert_eq!(table.keys_for("z"), &["b"]);
+        assert!(table.keys_for("y").is_empty());
+        assert_eq!(table.get("b"), Some("z"));
+        assert_eq!(table.entries().count(), 3);
+    }
+
+    #[test]
+    fn compare() {
+        let t1 = Table::new("t", vec![("a", "x")]);
+        assert_eq!(t1, t1.clone());
+        assert_eq!(t1, Table::new("t", vec![("a", "x")]));
+        assert_ne!(t1, Table::new("u", vec![("a", "x")]));
+        assert_ne!(t1, Table::new("t", vec![("a", "y")]));
+    }
+}
@@ -1,29 +1,60 @@
+use super::language::{char_offsets, write_quoted};
 use lazy_static::lazy_static;
-use regex::Regex;
+use regex::{Regex, RegexSet};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 
-#[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord)]
+/// A token that positions in a string can be anchored to.
+///
+/// Most tokens are character classes that match maximal runs of characters; see the BlinkFill
+/// paper for details. Callers can add their own tokens for patterns that are specific to their
+/// data with [`Learner::token`](super::Learner::token).
+#[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
 pub enum Token {
+    /// An uppercase letter followed by lowercase letters.
     ProperCase,
+    /// Uppercase letters.
     Caps,
+    /// Lowercase letters.
     Lowercase,
+    /// Digits.
     Digits,
+    /// Letters.
     Alphabets,
+    /// Letters and digits.
     Alphanumeric,
+    /// Whitespace.
     Whitespace,
+    /// The start of the string.
     Start,
+    /// The end of the string.
     End,
+    /// Proper case words separated by whitespace.
     ProperCaseWithSpaces,
+    /// Uppercase words separated by whitespace.
     CapsWithSpaces,
+    /// Lowercase words separated by whitespace.
     LowercaseWithSpaces,
+    /// Words separated by whitespace.
     AlphabetsWithSpaces,
+    /// A nonempty literal string.
+    #[non_exhaustive]
     Literal(String),
     // custom tokens (not from BlinkFill paper)
+    /// Characters other than `.`.
     NotDot, // useful for filename-based things, to match the name/extension
+    /// Characters other than whitespace.
+    NotWhitespace, // useful for loops, to match words including their punctuation
+    /// A token defined by the caller.
+    #[non_exhaustive]
+    Custom(CustomToken),
 }
 
 use Token::*;
 
-// 1-indexed, inclusive
+// 1-indexed, counting characters; the end is exclusive
 #[derive(Debug, PartialEq, Eq)]
 pub struct Span {
     pub start: usize,
@@ -45,8 +76,83 @@ pub const ALL_RE_TOKENS: &[Token] = &[
     NotDot,
 ];
 
+// the tokens that loops iterate over; NotWhitespace isn't used for positions, because it makes the
+// input data graph bigger without adding much, but it's what a loop over words needs
+pub const LOOP_TOKENS: &[Token] = &[
+    ProperCase,
+    Caps,
+    Lowercase,
+    Digits,
+    Alphabets,
+    Alphanumeric,
+    ProperCaseWithSpaces,
+    CapsWithSpaces,
+    LowercaseWithSpaces,
+    AlphabetsWithSpaces,
+    NotDot,
+    NotWhitespace,
+];
+
+// names follow the BlinkFill paper where possible
+pub(crate) const NAMES: &[(Token, &str)] = &[
+    (ProperCase, "ProperCase"),
+    (Caps, "CAPS"),
+    (Lowercase, "lowercase"),
+    (Digits, "Digits"),
+    (Alphabets, "Alphabets"),
+    (Alphanumeric, "Alphanumeric"),
+    (Whitespace, "Whitespace"),
+    (Start, "StartT"),
+    (End, "EndT"),
+    (ProperCaseWithSpaces, "ProperCaseWSpaces"),
+    (CapsWithSpaces, "CAPSWSpaces"),
+    (LowercaseWithSpaces, "lowercaseWSpaces"),
+    (AlphabetsWithSpaces, "AlphabetsWSpaces"),
+    (NotDot, "NotDot"),
+    (NotWhitespace, "NotWhitespace"),
+];
+
 impl Token {
-    pub fn weight(&self) -> usize {
+    // returns None for literal tokens, which don't have a name
+    pub(crate) fn name(&self) -> Option<&str> {
+        match self {
+            Custom(custom) => Some(&custom.name),
+            _ => NAMES.iter().find(|(t, _)| t == self).map(|(_, name)| *name),
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Token> {
+        NAMES
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(t, _)| t.clone())
+    }
+
+    pub(crate) fn literal_from(s: &str) -> Option<Token> {
+        if s.is_empty() {
+            None
+        } else {
+            Some(Literal(String::from(s)))
+        }
+    }
+
+    /// Returns the string if this is a literal token.
+    pub fn literal(&self) -> Option<&str> {
+        match self {
+            Literal(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the token definition if this is a custom token.
+    pub fn custom(&self) -> Option<&CustomToken> {
+        match self {
+            Custom(custom) => Some(custom),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn weight(&self) -> usize {
         match self {
             ProperCase => 70,
             Caps => 70,
@@ -61,69 +167,91 @@ impl Token {
             CapsWithSpaces => 60,
             LowercaseWithSpaces => 60,
             AlphabetsWithSpaces => 60,
-            Literal(s) => s.len(),
+            Literal(s) => s.chars().count(),
             NotDot => 90,
+            NotWhitespace => 60,
+            Custom(custom) => custom.weight,
         }
     }
 
-    pub fn all_matches(&self, s: &str) -> Vec<Span> {
-        let mut matches = Vec::new();
-        let mut offset = 0;
+    pub(crate) fn all_matches(&self, s: &str) -> Vec<Span> {
         match self {
             Start => {
                 // should only be looking at this span's end, in the start position of a substring
                 // op
-                matches.push(Span { start: 0, end: 1 });
+                vec![Span { start: 0, end: 1 }]
             }
             End => {
                 // should only be looking at this span's start, in the end position of a substring
                 // op
-                matches.push(Span {
-                    start: s.len() + 1,
-                    end: s.len() + 2,
-                });
-            }
-            Literal(tok_str) => {
-                let len = tok_str.len();
-                while offset < s.len() {
-                    let start = &s[offset..].find(tok_str);
-                    match start {
-                        None => {
-                            // no more matches
-                            return matches;
-                        }
-                        Some(start) => {
-                            let end = start + len;
-                            matches.push(Span {
-                                start: offset + start + 1,
-                                end: offset + end + 1,
-                            });
-                            offset += end;
-                        }
-                    }
-                }
+                let n = s.chars().count();
+                vec![Span {
+                    start: n + 1,
+                    end: n + 2,
+                }]
             }
             _ => {
-                let re = self.to_regex();
-                while offset < s.len() {
-                    let mat = re.find(&s[offset..]);
-                    match mat {
-                        None => {
-                            // no more matches
-                            return matches;
-                        }
-                        Some(mat) => {
-                            matches.push(Span {
-                                start: offset + mat.start() + 1,
-                                end: offset + mat.end() + 1,
-                            });
-                            offset += mat.end();
-                        }
-                    }
-                }
+                let offsets = if s.is_ascii() {
+                    None
+                } else {
+                    Some(char_offsets(s))
+                };
+                self.char_matches(s, offsets.as_deref())
             }
         }
-        matches
+    }
+
+    // the matches of a token other than the start and end, found by byte offset and converted to
+    // characters with the char_offsets of s, which are left out if s is ASCII
+    fn char_matches(&self, s: &str, offsets: Option<&[usize]>) -> Vec<Span> {
+        let to_span = |(start, end): (usize, usize)| match offsets {
+            Some(offsets) => Span {
+                start: offsets[start] + 1,
+                end: offsets[end] + 1,
+            },
+            None => Span {
+                start: start + 1,
+                end: end + 1,
+            },
+        };
+        match self {
+            Literal(tok_str) => s
+                .match_indices(tok_str.as_str())
+                .map(|(i, _)| to_span((i, i + tok_str.len())))
+                .collect(),
+            // custom tokens can match the empty string, which doesn't count as a match
+            _ => self
+                .to_regex()
+                .find_iter(s)
+                .filter(|m| !m.is_empty())
+                .map(|m| to_span((m.start(), m.end())))
+                .collect(),
+        }
+    }
+
+    // finds the matches of the regex tokens and the given custom tokens in s, leaving out the
+    // tokens that don't match; one pass over s with all of the built-in tokens at once finds the
+    // ones that match anywhere, so only those are searched for
+    pub(crate) fn all_re_matches<'a>(
+        s: &str,
+        custom_tokens: &'a [Token],
+    ) -> Vec<(&'a Token, Vec<Span>)> {
+        lazy_static! {
+            static ref SET: RegexSet =
+                RegexSet::new(ALL_RE_TOKENS.iter().map(|t| t.to_regex().as_str())).unwrap();
+        }
+        let offsets = if s.is_ascii() {
+            None
+        } else {
+            Some(char_offsets(s))
+        };
+        SET.matches(s)
+            .into_iter()
+            .map(|i| &ALL_RE_TOKENS[i])
+            .chain(custom_tokens)
+            .map(|token| (token, token.char_matches(s, offsets.as_deref())))
+            .filter(|(_, matches)| !matches.is_empty())
+            .collect()
     }
 
     fn to_regex(&self) -> &Regex {
@@ -204,6 +332,133 @@ impl Token {
                 }
                 &RE
             }
+            NotWhitespace => {
+                lazy_static! {
+                    static ref RE: Regex = Regex::new(r"\S+").unwrap();
+                }
+                &RE
+            }
+            Custom(custom) => &custom.regex,
+        }
+    }
+}
+
+/// A token defined by a regular expression, for patterns that are specific to the data, like
+/// product codes or hashes.
+///
+/// Custom tokens are passed to the learner with [`Learner::token`](super::Learner::token), and
+/// learned programs can anchor positions to their matches, just like the built-in tokens. A token
+/// matches the non-overlapping matches of its regular expression, from left to right; empty
+/// matches are ignored.
+///
+/// Tokens are identified by name in the DSL, so the name must be an identifier (ASCII letters,
+/// digits, and underscores, not starting with a digit) that is different from the names of the
+/// built-in tokens, and programs that use custom tokens are parsed with
+/// [`Program::parse_with`](super::Program::parse_with).
+///
+/// # Example
+///
+/// ```
+/// use regex::Regex;
+/// use synox::blinkfill::CustomToken;
+///
+/// let sku = CustomToken::new("SKU", Regex::new(r"[A-Z]{2}-\d{4}-[A-Z]").unwrap(), 90).unwrap();
+/// assert_eq!(sku.name(), "SKU");
+/// assert_eq!(sku.regex().as_str(), r"[A-Z]{2}-\d{4}-[A-Z]");
+/// ```
+#[derive(Debug, Clone)]
+pub struct CustomToken {
+    name: String,
+    regex: Regex,
+    weight: usize,
+}
+
+impl CustomToken {
+    /// Creates a token with the given name that matches the given regular expression.
+    ///
+    /// The weight determines how much the token is preferred as an anchor for positions, compared
+    /// to other tokens that match in the same place. The built-in character classes have weights
+    /// between 60 and 90, and literal tokens have their length as their weight.
+    ///
+    /// Returns an error message if the name isn't an identifier or is the name of a built-in
+    /// token, because programs that use the token couldn't be parsed back.
+    pub fn new(name: impl Into<String>, regex: Regex, weight: usize) -> Result<Self, String> {
+        let name = name.into();
+        let mut chars = name.chars();
+        let identifier = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !identifier {
+            return Err(format!("token name `{}` is not an identifier", name));
+        }
+        if Token::from_name(&name).is_some() {
+            return Err(format!(
+                "token name `{}` is taken by a built-in token",
+                name
+            ));
+        }
+        Ok(Self {
+            name,
+            regex,
+            weight,
+        })
+    }
+
+    /// Returns the name of the token.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the regular expression that the token matches.
+    pub fn regex(&self) -> &Regex {
+        &self.regex
+    }
+
+    /// Returns the weight of the token.
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+}
+
+// custom tokens are compared and hashed by name, pattern, and weight, because regexes can't be
+// compared
+
+impl PartialEq for CustomToken {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for CustomToken {}
+
+impl PartialOrd for CustomToken {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CustomToken {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&self.name, self.regex.as_str(), self.weight).cmp(&(
+            &other.name,
+            other.regex.as_str(),
+            other.weight,
+        ))
+    }
+}
+
+impl Hash for CustomToken {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (&self.name, self.regex.as_str(), self.weight).hash(state);
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Literal(s) => write_quoted(f, s),
+            _ => f.write_str(self.name().unwrap()),
         }
     }
 }
@@ -211,6 +466,109 @@ impl Token {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn names() {
+        for (token, name) in NAMES {
+            assert_eq!(token.name(), Some(*name));
+            assert_eq!(Token::from_name(name).as_ref(), Some(token));
+        }
+        assert_eq!(Literal(String::from("x")).name(), None);
+        assert_eq!(Token::from_name("x"), None);
+        assert_eq!(Token::literal_from(""), None);
+    }
+
+    #[test]
+    fn custom() {
+        let hex =
+            Custom(CustomToken::new("Hex", Regex::new(r"[0-9a-f]{6,}").unwrap(), 80).unwrap());
+        assert_eq!(hex.name(), Some("Hex"));
+        assert_eq!(hex.weight(), 80);
+        assert_eq!(hex.to_string(), "Hex");
+        let matches = hex.all_matches("commit 3fa4c2e and 9b2d07a1, not 12ab");
+        let spans: Vec<_> = matches.iter().map(|m| (m.start, m.end)).collect();
+        assert_eq!(spans, vec![(8, 15), (20, 28)]);
+        // empty matches are skipped
+        let empty = Custom(CustomToken::new("Empty", Regex::new(r"x*").unwrap(), 1).unwrap());
+        let spans: Vec<_> = empty
+            .all_matches("axxbé")
+            .iter()
+            .map(|m| (m.start, m.end))
+            .collect();
+        assert_eq!(spans, vec![(2, 4)]);
+    }
+
+    #[test]
+    fn custom_names() {
+        let new = |name| CustomToken::new(name, Regex::new("x").unwrap(), 1);
+        assert!(new("_Hex2").is_ok());
+        // names that couldn't be parsed back are rejected
+        assert_eq!(
+            new("my token").unwrap_err(),
+            "token name `my token` is not an identifier"
+        );
+        assert!(new("2x").is_err());
+        assert!(new("").is_err());
+        assert_eq!(
+            new("Digits").unwrap_err(),
+            "token name `Digits` is taken by a built-in token"
+        );
+    }
+
+    #[test]
+    fn char_indices() {
+        // spans count characters, not bytes
+        let spans = |token: &Token, s| -> Vec<_> {
+            token
+                .all_matches(s)
+                .iter()
+                .map(|m| (m.start, m.end))
+                .collect()
+        };
+        assert_eq!(spans(&Digits, "né à 42"), vec![(6, 8)]);
+        assert_eq!(
+            spans(&Literal(String::from("京")), "東京, 京都"),
+            vec![(2, 3), (5, 6)]
+        );
+        assert_eq!(spans(&End, "Zoë"), vec![(4, 5)]);
+        assert_eq!(Literal(String::from("ñu")).weight(), 2);
+    }
+
+    #[test]
+    fn all_re_matches() {
+        let hex = Custom(CustomToken::new("Hex", Regex::new("0x[0-9a-f]+").unwrap(), 80).unwrap());
+        let s = "Übung 12 at 0xbeef";
+        let found = Token::all_re_matches(s, std::slice::from_ref(&hex));
+        let expected: Vec<&Token> = ALL_RE_TOKENS
+            .iter()
+            .chain([&hex])
+            .filter(|t| !t.all_matches(s).is_empty())
+            .collect();
+        assert_eq!(found.iter().map(|(t, _)| *t).collect::<Vec<_>>(), expected);
+        for (token, matches) in found {
+            assert_eq!(matches, token.all_matches(s));
+        }
+        let found: Vec<&Token> = Token::all_re_matches("12 34", &[])
+            .into_iter()
+            .map(|(t, _)| t)
+            .collect();
+        assert_eq!(found, vec![&Digits, &Alphanumeric, &Whitespace, &NotDot]);
+        // nothing matches the empty string
+        assert!(Token::all_re_matches("", &[]).is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn all_re_matches_agree_with_regexes(s in "[aZb Y.9éÉ\u{2003}\u{a0}ǅß٣\t_-]{0,40}") {
+            let found = Token::all_re_matches(&s, &[]);
+            for token in ALL_RE_TOKENS {
+                let expected = token.all_matches(&s);
+                let matches = found.iter().find(|(t, _)| *t == token).map(|(_, m)| m.as_slice());
+                prop_assert_eq!(matches.unwrap_or_default(), expected.as_slice(), "{:?}", token);
+            }
+        }
+    }
 
     #[test]
     fn to_regex() {
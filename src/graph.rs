@@ -4,6 +4,8 @@ use std::ops::Add;
 
 type Edge<N> = (N, N);
 type AdjacencyMap<N> = BTreeMap<N, BTreeSet<N>>;
+// a path through a multigraph, with the index of the parallel edge taken at every step
+type MultiPath<N> = Vec<(Edge<N>, usize)>;
 
 /// Computes an adjacency map from a set of directed edges.
 ///
@@ -134,7 +136,88 @@ where
     Some(path)
 }
 
+/// Computes the k shortest paths between two nodes in a directed acyclic multigraph.
+///
+/// There may be several parallel edges from one node to another; `costs` returns the costs of all
+/// of the edges between a pair of nodes, and each edge in a path is returned together with the
+/// index of the parallel edge that was taken. Paths are returned in order of increasing cost, with
+/// at most k paths in total. With k = 1, this finds the same path as [`shortest_path_dag`] when
+/// every pair of nodes has a single edge between them.
+///
+/// The result is undefined if the graph contains cycles.
+pub fn k_shortest_paths_dag<N, F, C>(
+    start: &N,
+    end: &N,
+    adj: &AdjacencyMap<N>,
+    k: usize,
+    mut costs: F,
+) -> Vec<(C, MultiPath<N>)>
+where
+    N: Eq + Copy + Ord,
+    F: FnMut(&N, &N) -> Vec<C>,
+    C: Copy + Ord + Add<Output = C>,
+{
+    let inv = invert_adjacency_map(adj);
+    // same DP as shortest_path_dag, but we keep the k best partial paths to every node; each
+    // partial path is stored as (cost, predecessor, parallel edge, rank of the partial path to the
+    // predecessor), where the rank is None for paths coming straight from the start node
+    let topo = topological_sort(adj);
+    let index: BTreeMap<_, _> = topo.iter().enumerate().map(|(i, v)| (v, i)).collect();
+    if k == 0 || !index.contains_key(start) || !index.contains_key(end) {
+        return Vec::new();
+    }
+    let start_i = index[start];
+    let end_i = index[end];
+    if end_i <= start_i {
+        return Vec::new();
+    }
+    type PartialPath<C> = (C, usize, usize, Option<usize>);
+    let mut best: Vec<Vec<PartialPath<C>>> = Vec::new();
+    let empty = BTreeSet::new();
+    for i in start_i + 1..end_i + 1 {
+        let here = &topo[i];
+        let preds = inv.get(here).unwrap_or(&empty);
+        let mut candidates = Vec::new();
+        if preds.contains(start) {
+            for (alt, c) in costs(start, here).into_iter().enumerate() {
+                candidates.push((c, start_i, alt, None));
+            }
+        }
+        for j in start_i + 1..i {
+            let intermediate = &topo[j];
+            if preds.contains(intermediate) && !best[j - start_i - 1].is_empty() {
+                for (alt, c) in costs(intermediate, here).into_iter().enumerate() {
+                    for (rank, (c_prev, _, _, _)) in best[j - start_i - 1].iter().enumerate() {
+                        candidates.push((*c_prev + c, j, alt, Some(rank)));
+                    }
+                }
+            }
+        }
+        // stable, so ties are broken in favor of earlier predecessors, like in shortest_path_dag
+        candidates.sort_by_key(|c| c.0);
+        candidates.truncate(k);
+        best.push(candidates);
+    }
+    // reconstruct the actual paths
+    let mut paths = Vec::new();
+    for (rank, (c, _, _, _)) in best[end_i - start_i - 1].iter().enumerate() {
+        let mut path = Vec::new();
+        let mut curr_i = end_i;
+        let mut rank = Some(rank);
+        while let Some(r) = rank {
+            let (_, prev_i, alt, prev_rank) = best[curr_i - start_i - 1][r];
+            path.push(((topo[prev_i], topo[curr_i]), alt));
+            curr_i = prev_i;
+            rank = prev_rank;
+        }
+        path.reverse();
+        paths.push((*c, path));
+    }
+    paths
+}
+
 #[cfg(test)]
+#[allow(clippy::useless_vec)]
 mod tests {
     use super::*;
 
@@ -200,4 +283,32 @@ mod tests {
         });
         assert_eq!(path.unwrap(), vec![(5, 2), (2, 3), (3, 1)]);
     }
+
+    #[test]
+    fn test_k_shortest_paths_dag() {
+        let edges = [(5, 2), (5, 0), (4, 0), (4, 1), (2, 3), (3, 1), (5, 3)];
+        let adj = adjacency_map(edges.iter());
+        let paths = k_shortest_paths_dag(&5, &1, &adj, 3, |n1, n2| match (*n1, *n2) {
+            (5, 3) => vec![5, 2],
+            _ => vec![1],
+        });
+        assert_eq!(
+            paths,
+            vec![
+                (3, vec![((5, 3), 1), ((3, 1), 0)]),
+                (3, vec![((5, 2), 0), ((2, 3), 0), ((3, 1), 0)]),
+                (6, vec![((5, 3), 0), ((3, 1), 0)]),
+            ]
+        );
+        // agrees with shortest_path_dag for a single path
+        let path = k_shortest_paths_dag(&5, &1, &adj, 1, |_, _| vec![1]);
+        let path: Vec<_> = path[0].1.iter().map(|(e, _)| *e).collect();
+        assert_eq!(Some(path), shortest_path_dag(&5, &1, &adj, |_, _| 1));
+        // fewer than k paths
+        assert_eq!(
+            k_shortest_paths_dag(&5, &1, &adj, 10, |_, _| vec![1]).len(),
+            2
+        );
+        assert!(k_shortest_paths_dag(&2, &0, &adj, 10, |_, _| vec![1]).is_empty());
+    }
 }
@@ -37,9 +37,9 @@
 //!
 //! // learn a program based on input-output examples
 //! //
-//! // blinkfill::learn returns an Option because it may fail to learn a program that matches all
+//! // blinkfill::learn returns a Result because it may fail to learn a program that matches all
 //! // the examples
-//! let prog = blinkfill::learn(unpaired, examples)?;
+//! let prog = blinkfill::learn(unpaired, examples).ok()?;
 //!
 //! // StringProgram::run returns an Option because the program may fail on a particular input
 //! let result = prog.run(&["Cy D. Fect", "2017"])?;
@@ -56,6 +56,7 @@
 
 pub mod blinkfill;
 mod graph;
+mod par;
 
 /// A program that transforms a list of strings into a string.
 ///
@@ -0,0 +1,112 @@
+use std::ops::Range;
+
+// applies a function to every index in a range, in parallel with the rayon feature; the results
+// are in the order of the indices
+pub fn map<U, F>(range: Range<usize>, f: F) -> Vec<U>
+where
+    U: Send,
+    F: Fn(usize) -> U + Sync + Send,
+{
+    #[cfg(feature = "rayon")]
+    {
+        use rayon::prelude::*;
+        range.into_par_iter().map(f).collect()
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        range.map(f).collect()
+    }
+}
+
+// applies a function to every index in a range and combines the results with an associative
+// function, or returns None for an empty range; without the rayon feature the results are folded
+// from the left, one at a time
+pub fn map_reduce<U, M, R>(range: Range<usize>, map: &M, reduce: &R) -> Option<U>
+where
+    U: Send,
+    M: Fn(usize) -> U + Sync,
+    R: Fn(U, U) -> U + Sync,
+{
+    #[cfg(feature = "rayon")]
+    {
+        balanced_map_reduce(range, map, reduce)
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        range.map(map).reduce(reduce)
+    }
+}
+
+// like map_reduce, but the results are combined with a first value that comes before all of them
+pub fn map_fold<U, M, R>(first: U, range: Range<usize>, map: &M, reduce: &R) -> U
+where
+    U: Send,
+    M: Fn(usize) -> U + Sync,
+    R: Fn(U, U) -> U + Sync,
+{
+    #[cfg(feature = "rayon")]
+    {
+        match balanced_map_reduce(range, map, reduce) {
+            Some(rest) => reduce(first, rest),
+            None => first,
+        }
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        range.map(map).fold(first, reduce)
+    }
+}
+
+// reduces the two halves of the range in parallel and then combines them, so the results are
+// combined as a balanced tree and only the ones along the current path through it are kept
+#[cfg(feature = "rayon")]
+fn balanced_map_reduce<U, M, R>(range: Range<usize>, map: &M, reduce: &R) -> Option<U>
+where
+    U: Send,
+    M: Fn(usize) -> U + Sync,
+    R: Fn(U, U) -> U + Sync,
+{
+    match range.len() {
+        0 => None,
+        1 => Some(map(range.start)),
+        n => {
+            let mid = range.start + n / 2;
+            let (left, right) = rayon::join(
+                || balanced_map_reduce(range.start..mid, map, reduce),
+                || balanced_map_reduce(mid..range.end, map, reduce),
+            );
+            // both halves are nonempty
+            Some(reduce(left?, right?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_reduce_order() {
+        let leaf = |i: usize| i.to_string();
+        let concat = |a: String, b: String| format!("({} {})", a, b);
+        assert_eq!(map_reduce(0..0, &leaf, &concat), None);
+        assert_eq!(map_reduce(3..4, &leaf, &concat), Some(String::from("3")));
+        let expected = if cfg!(feature = "rayon") {
+            "((0 1) (2 (3 4)))"
+        } else {
+            "((((0 1) 2) 3) 4)"
+        };
+        assert_eq!(
+            map_reduce(0..5, &leaf, &concat),
+            Some(String::from(expected))
+        );
+        let expected = if cfg!(feature = "rayon") {
+            "(x (1 (2 3)))"
+        } else {
+            "(((x 1) 2) 3)"
+        };
+        assert_eq!(map_fold(String::from("x"), 1..4, &leaf, &concat), expected);
+        assert_eq!(map_fold(String::from("x"), 1..1, &leaf, &concat), "x");
+        assert_eq!(map(2..5, |i| i * i), vec![4, 9, 16]);
+    }
+}